@@ -2,7 +2,7 @@
     borrow::Cow,
     cmp::Ordering,
     collections::BTreeSet,
-    ops::Range,
+    ops::{Range, RangeBounds},
     sync::{
         atomic::{self, AtomicU64},
         Arc,
@@ -12,10 +12,9 @@
 use lapce_xi_rope::{
     delta::InsertDelta,
     diff::{Diff, LineHashDiff},
-    interval::IntervalBounds,
     multiset::{CountMatcher, Subset},
     tree::{Node, NodeInfo},
-    Cursor, Delta, DeltaBuilder, DeltaElement, Interval, Rope, RopeDelta, RopeInfo,
+    Delta, DeltaBuilder, DeltaElement, Interval, Rope, RopeDelta, RopeInfo,
 };
 use lsp_types::Position;
 
@@ -199,6 +198,10 @@ pub fn num_lines(&self) -> usize {
         RopeText::new(&self.text).num_lines()
     }
 
+    pub fn last_line_number_width(&self, base: u32) -> usize {
+        RopeText::new(&self.text).last_line_number_width(base)
+    }
+
     fn get_max_line_len(&self) -> (usize, usize) {
         let mut pre_offset = 0;
         let mut max_len = 0;
@@ -252,6 +255,10 @@ pub fn line_len(&self, line: usize) -> usize {
         RopeText::new(&self.text).line_len(line)
     }
 
+    pub fn line_len_without_ending(&self, line: usize) -> usize {
+        RopeText::new(&self.text).line_len_without_ending(line)
+    }
+
     pub fn init_content(&mut self, content: Rope) {
         if !content.is_empty() {
             let delta = Delta::simple_edit(Interval::new(0, 0), content, 0);
@@ -789,6 +796,10 @@ pub fn line_content(&self, line: usize) -> Cow<str> {
         RopeText::new(&self.text).line_content(line)
     }
 
+    pub fn line_content_trimmed(&self, line: usize) -> Cow<str> {
+        RopeText::new(&self.text).line_content_trimmed(line)
+    }
+
     pub fn prev_grapheme_offset(
         &self,
         offset: usize,
@@ -840,25 +851,7 @@ pub fn next_grapheme_offset(
         count: usize,
         limit: usize,
     ) -> usize {
-        let offset = if offset > self.len() {
-            self.len()
-        } else {
-            offset
-        };
-        let mut cursor = Cursor::new(&self.text, offset);
-        let mut new_offset = offset;
-        for _i in 0..count {
-            if let Some(next_offset) = cursor.next_grapheme() {
-                if next_offset > limit {
-                    return new_offset;
-                }
-                new_offset = next_offset;
-                cursor.set(next_offset);
-            } else {
-                return new_offset;
-            }
-        }
-        new_offset
+        RopeText::new(&self.text).next_grapheme_offset(offset, count, limit)
     }
 
     pub fn is_empty(&self) -> bool {
@@ -897,17 +890,16 @@ pub fn previous_unmatched(
     /// You should be somewhat wary of requesting large parts of the rope, as it will allocate
     /// a new string since it isn't contiguous in memory for large chunks.
     pub fn slice_to_cow(&self, range: Range<usize>) -> Cow<str> {
-        self.text
-            .slice_to_cow(range.start.min(self.len())..range.end.min(self.len()))
+        RopeText::new(&self.text).slice_to_cow(range)
     }
 
     /// Iterate over (utf8_offset, char) values in the given range  
     /// This uses `iter_chunks` and so does not allocate, compared to `slice_to_cow` which can
-    pub fn char_indices_iter<T: IntervalBounds>(
+    pub fn char_indices_iter<T: RangeBounds<usize>>(
         &self,
         range: T,
     ) -> impl Iterator<Item = (usize, char)> + '_ {
-        CharIndicesJoin::new(self.text.iter_chunks(range).map(str::char_indices))
+        RopeText::new(&self.text).char_indices_iter(range)
     }
 
     pub fn len(&self) -> usize {
@@ -945,38 +937,8 @@ pub fn move_n_paragraphs_backward(&self, offset: usize, count: usize) -> usize {
         self.find_nth_paragraph(offset, count, |cursor| cursor.prev_boundary())
     }
 
-    /// Find the nth (`count`) word starting at `offset` in either direction
-    /// depending on `find_next`.
-    ///
-    /// A `WordCursor` is created and given to the `find_next` function for the
-    /// search.  The `find_next` function should return None when there is no
-    /// more word found.  Despite the name, `find_next` can search in either
-    /// direction.
-    fn find_nth_word<F>(
-        &self,
-        offset: usize,
-        mut count: usize,
-        mut find_next: F,
-    ) -> usize
-    where
-        F: FnMut(&mut WordCursor) -> Option<usize>,
-    {
-        let mut cursor = WordCursor::new(self.text(), offset);
-        let mut new_offset = offset;
-        while count != 0 {
-            // FIXME: wait for if-let-chain
-            if let Some(offset) = find_next(&mut cursor) {
-                new_offset = offset;
-            } else {
-                break;
-            }
-            count -= 1;
-        }
-        new_offset
-    }
-
     pub fn move_n_words_forward(&self, offset: usize, count: usize) -> usize {
-        self.find_nth_word(offset, count, |cursor| cursor.next_boundary())
+        RopeText::new(&self.text).move_n_words_forward(offset, count)
     }
 
     pub fn move_n_wordends_forward(
@@ -986,7 +948,8 @@ pub fn move_n_wordends_forward(
         inserting: bool,
     ) -> usize {
         let mut new_offset =
-            self.find_nth_word(offset, count, |cursor| cursor.end_boundary());
+            RopeText::new(&self.text)
+                .find_nth_word(offset, count, |cursor| cursor.end_boundary());
         if !inserting && new_offset != self.len() {
             new_offset = self.prev_grapheme_offset(new_offset, 1, 0);
         }
@@ -999,11 +962,12 @@ pub fn move_n_words_backward(
         count: usize,
         mode: Mode,
     ) -> usize {
-        self.find_nth_word(offset, count, |cursor| cursor.prev_boundary(mode))
+        RopeText::new(&self.text).move_n_words_backward(offset, count, mode)
     }
 
     pub fn move_word_backward_deletion(&self, offset: usize) -> usize {
-        self.find_nth_word(offset, 1, |cursor| cursor.prev_deletion_boundary())
+        RopeText::new(&self.text)
+            .find_nth_word(offset, 1, |cursor| cursor.prev_deletion_boundary())
     }
 }
 
@@ -1,21 +1,130 @@
-use std::{borrow::Cow, ops::Range};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ops::{Bound, Range, RangeBounds},
+};
 
-use lapce_xi_rope::{interval::IntervalBounds, Cursor, Rope};
+use itertools::Either;
+use lapce_xi_rope::{
+    find::{find, CaseMatching},
+    rope::Utf16CodeUnitsMetric,
+    Cursor, LinesMetric, Rope, RopeInfo,
+};
 use lsp_types::Position;
+use regex::Regex;
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
     encoding::{offset_utf16_to_utf8, offset_utf8_to_utf16},
-    word::WordCursor,
+    mode::Mode,
+    syntax::util::{matching_char, matching_pair_direction},
+    word::{get_char_property, CharClassification, WordCursor},
 };
 
+/// The line ending at the end of a line.  
+/// A lone `\r` is not treated as a line ending, since the rope only splits lines on `\n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    /// Get the line ending at the end of the given line content, if there is one
+    fn of_line(content: &str) -> Option<LineEnding> {
+        if content.ends_with("\r\n") {
+            Some(LineEnding::CrLf)
+        } else if content.ends_with('\n') {
+            Some(LineEnding::Lf)
+        } else {
+            None
+        }
+    }
+}
+
+/// The leading whitespace of a line, along with how it is made up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndentInfo {
+    /// The indentation itself
+    pub text: String,
+    /// The visual width of the indentation, with tabs expanded
+    pub width: usize,
+    pub uses_tabs: bool,
+    pub uses_spaces: bool,
+}
+
+impl IndentInfo {
+    /// Whether the indentation mixes tabs and spaces
+    pub fn is_mixed(&self) -> bool {
+        self.uses_tabs && self.uses_spaces
+    }
+}
+
+/// A difference between the lines of two texts, from `RopeText::line_diff`.  
+/// The ranges are of line numbers, in the old and new text respectively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineChange {
+    /// The `new` lines were inserted before line `old_line` of the old text
+    Inserted { old_line: usize, new: Range<usize> },
+    /// The `old` lines were removed, and would have been before line `new_line` of the new
+    /// text
+    Removed { old: Range<usize>, new_line: usize },
+    /// The `old` lines were replaced by the `new` lines
+    Changed {
+        old: Range<usize>,
+        new: Range<usize>,
+    },
+}
+
+impl LineChange {
+    fn new(old: Range<usize>, new: Range<usize>) -> LineChange {
+        if old.is_empty() {
+            LineChange::Inserted {
+                old_line: old.start,
+                new,
+            }
+        } else if new.is_empty() {
+            LineChange::Removed {
+                old,
+                new_line: new.start,
+            }
+        } else {
+            LineChange::Changed { old, new }
+        }
+    }
+}
+
 /// A wrapper around a rope that provides utility functions atop it.
 pub struct RopeText<'a> {
     text: &'a Rope,
+    /// Whether the empty line after a final line ending counts as a line
+    trailing_empty_line: bool,
 }
 
 impl<'a> RopeText<'a> {
     pub fn new(text: &'a Rope) -> Self {
-        Self { text }
+        Self {
+            text,
+            trailing_empty_line: true,
+        }
+    }
+
+    /// Don't count the empty line after a final line ending as a line.  
+    /// For example, `"a\n"` is then considered to have one line rather than two. This only
+    /// changes `last_line` and `num_lines` (and thus where lines are considered out of bounds),
+    /// the offsets of lines are unaffected.
+    pub fn without_trailing_empty_line(mut self) -> Self {
+        self.trailing_empty_line = false;
+        self
     }
 
     pub fn len(&self) -> usize {
@@ -26,9 +135,34 @@ pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    /// Whether the text starts with a UTF-8 byte order mark, as files written on Windows
+    /// often do.
+    pub fn starts_with_bom(&self) -> bool {
+        self.bytes_in_range(0..3).eq([0xEF, 0xBB, 0xBF])
+    }
+
+    /// The offset where the content starts, which is after the byte order mark if there is
+    /// one, so that it can be skipped when computing columns or sending the content to an
+    /// LSP.
+    pub fn content_start_offset(&self) -> usize {
+        if self.starts_with_bom() {
+            3
+        } else {
+            0
+        }
+    }
+
     /// The last line of the held rope
     pub fn last_line(&self) -> usize {
-        self.line_of_offset(self.len())
+        let last_line = self.line_of_offset(self.len());
+        if !self.trailing_empty_line
+            && last_line > 0
+            && self.text.byte_at(self.len() - 1) == b'\n'
+        {
+            return last_line - 1;
+        }
+
+        last_line
     }
 
     /// Get the offset into the rope of the start of the given line.  
@@ -39,6 +173,72 @@ pub fn offset_of_line(&self, line: usize) -> usize {
         self.text.offset_of_line(line)
     }
 
+    /// Get the change in length and in number of lines that replacing the range with
+    /// `new_text` would make, without editing the rope, so that line and offset bookkeeping
+    /// can be updated before the edit is applied.  
+    /// The range is clamped like `slice_to_cow`. Lines are counted by their `\n`, so a
+    /// `\r\n` counts once.
+    pub fn preview_replace(
+        &self,
+        range: Range<usize>,
+        new_text: &str,
+    ) -> (isize, isize) {
+        let Range { start, end } = self.clamp_range(range);
+        let removed_lines = self.line_of_offset(end) - self.line_of_offset(start);
+        let added_lines = new_text.bytes().filter(|&b| b == b'\n').count();
+
+        let len_delta = new_text.len() as isize - (end - start) as isize;
+        let line_delta = added_lines as isize - removed_lines as isize;
+        (len_delta, line_delta)
+    }
+
+    /// Get the offset of the start of the line `n` lines after the line containing `offset`,
+    /// like `offset_of_line(line_of_offset(offset) + n)`, but by stepping a cursor forward
+    /// from `offset` rather than descending the rope from the top, which is cheaper for
+    /// small `n`, such as when scrolling by a page.  
+    /// If that line is out of bounds, then the last offset (the len) is returned.
+    pub fn line_start_after(&self, offset: usize, n: usize) -> usize {
+        let (_, line_start) = self.line_and_start_of_offset(offset);
+        let mut cursor = Cursor::new(self.text, line_start);
+        let mut line_start = line_start;
+        for _ in 0..n {
+            match cursor.next::<LinesMetric>() {
+                Some(next_line_start) => line_start = next_line_start,
+                None => return self.len(),
+            }
+        }
+
+        line_start
+    }
+
+    /// Get the offsets of the start and end of the line, where the end is after the line
+    /// ending, equivalent to `(offset_of_line(line), offset_of_line(line + 1))` but with a
+    /// single descent into the rope.  
+    /// Lines past the end give `(self.len(), self.len())`.
+    pub fn line_offset_bounds(&self, line: usize) -> (usize, usize) {
+        if line > self.last_line() {
+            return (self.len(), self.len());
+        }
+
+        let start = self.text.offset_of_line(line);
+        let mut cursor = Cursor::new(self.text, start);
+        let end = cursor.next::<LinesMetric>().unwrap_or_else(|| self.len());
+        (start, end)
+    }
+
+    /// Get the range of offsets covered by the block of lines, from the start of the first
+    /// line to the end of the last line (after its line ending).  
+    /// Lines are clamped like `offset_of_line`, and an empty or inverted range of lines gives
+    /// an empty range at the start of `lines.start`.
+    pub fn line_range_offsets(&self, lines: Range<usize>) -> Range<usize> {
+        let start = self.offset_of_line(lines.start);
+        if lines.end <= lines.start {
+            return start..start;
+        }
+
+        start..self.offset_of_line(lines.end)
+    }
+
     pub fn offset_line_end(&self, offset: usize, caret: bool) -> usize {
         let line = self.line_of_offset(offset);
         self.line_end_offset(line, caret)
@@ -54,14 +254,49 @@ pub fn line_of_offset(&self, offset: usize) -> usize {
         self.text.line_of_offset(offset)
     }
 
+    /// Get the offset of the start of the codepoint after `offset`, or `offset` itself if it
+    /// is already on a codepoint boundary.  
+    /// The rope's `at_or_next_codepoint_boundary` only steps a single byte when the offset is in
+    /// the middle of a codepoint, so we step back to the start of it and then over it instead.
+    fn at_or_next_codepoint_boundary(&self, offset: usize) -> usize {
+        let offset = offset.min(self.len());
+        match self.text.at_or_prev_codepoint_boundary(offset) {
+            Some(prev) if prev < offset => {
+                self.text.next_codepoint_offset(prev).unwrap_or(offset)
+            }
+            _ => offset,
+        }
+    }
+
+    /// If the offset is between the `\r` and `\n` of a CRLF, then get the offset before the
+    /// `\r`, otherwise the offset is returned unchanged.
+    fn snap_out_of_crlf(&self, offset: usize) -> usize {
+        if offset > 0
+            && offset < self.len()
+            && self.text.byte_at(offset - 1) == b'\r'
+            && self.text.byte_at(offset) == b'\n'
+        {
+            offset - 1
+        } else {
+            offset
+        }
+    }
+
+    /// Get the line of the offset along with the offset of the start of that line.  
+    /// This avoids the extra clamping work that `offset_of_line` does, since the line we get
+    /// from `line_of_offset` is always in bounds.
+    fn line_and_start_of_offset(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_of_offset(offset);
+        (line, self.text.offset_of_line(line))
+    }
+
     /// Converts a UTF8 offset to a UTF16 LSP position  
-    /// Returns None if it is not a valid UTF16 offset
+    /// If the offset is in the middle of a codepoint, then it is snapped back to the start of
+    /// that codepoint, so the position never lands between the halves of a surrogate pair.
+    /// An offset between the `\r` and `\n` of a CRLF line ending gives the position before
+    /// the `\r`, like `offset_to_line_col`.
     pub fn offset_to_position(&self, offset: usize) -> Position {
-        let (line, col) = self.offset_to_line_col(offset);
-        let line_offset = self.offset_of_line(line);
-
-        let utf16_col =
-            offset_utf8_to_utf16(self.char_indices_iter(line_offset..), col);
+        let (line, utf16_col) = self.line_utf16col_of_offset(offset);
 
         Position {
             line: line as u32,
@@ -69,54 +304,196 @@ pub fn offset_to_position(&self, offset: usize) -> Position {
         }
     }
 
+    /// Get the line and UTF16 column of the offset, like `offset_to_position` but without
+    /// constructing a `Position`.  
+    /// An offset between the `\r` and `\n` of a CRLF line ending gives the column before
+    /// the `\r`, like `offset_to_line_col`.
+    pub fn line_utf16col_of_offset(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.len());
+        let offset = self
+            .text
+            .at_or_prev_codepoint_boundary(offset)
+            .unwrap_or(offset);
+        let offset = self.snap_out_of_crlf(offset);
+        let (line, line_offset) = self.line_and_start_of_offset(offset);
+        let col = offset - line_offset;
+
+        let utf16_col =
+            offset_utf8_to_utf16(self.char_indices_iter(line_offset..), col);
+
+        (line, utf16_col)
+    }
+
+    /// Get the offset of the UTF16 column on the line, like `offset_of_position` but without
+    /// constructing a `Position`. Out of range lines and columns are clamped the same way.
+    pub fn offset_of_line_utf16col(&self, line: usize, utf16_col: usize) -> usize {
+        let col = self.utf16_col_to_col(line, utf16_col);
+        self.offset_of_line_col(line, col)
+    }
+
+    /// Get the number of UTF16 code units from the start of the text to the UTF8 offset, for
+    /// protocols which want an absolute UTF16 offset rather than a line and column.  
+    /// This uses the UTF16 lengths the rope keeps for its nodes, so it doesn't walk the
+    /// characters before the offset. An offset in the middle of a codepoint is snapped back
+    /// to the start of it.
+    pub fn utf16_offset_of(&self, utf8_offset: usize) -> usize {
+        let offset = utf8_offset.min(self.len());
+        let offset = self
+            .text
+            .at_or_prev_codepoint_boundary(offset)
+            .unwrap_or(offset);
+        // The rope's conversion doesn't handle the very end of the text
+        if offset == self.len() {
+            self.text.measure::<Utf16CodeUnitsMetric>()
+        } else {
+            self.text.count::<Utf16CodeUnitsMetric>(offset)
+        }
+    }
+
+    /// Get the UTF8 offset of the absolute UTF16 offset, the inverse of `utf16_offset_of`.  
+    /// An offset in the middle of a surrogate pair is moved to the end of the character,
+    /// and an offset past the end of the text gives `self.len()`.
+    pub fn utf8_offset_of_utf16(&self, utf16_offset: usize) -> usize {
+        if utf16_offset >= self.text.measure::<Utf16CodeUnitsMetric>() {
+            return self.len();
+        }
+
+        self.text
+            .count_base_units::<Utf16CodeUnitsMetric>(utf16_offset)
+    }
+
+    /// Converts a UTF16 LSP position to a UTF8 offset.  
+    /// Out of range positions are clamped: a `character` past the end of the line gives the
+    /// offset of the end of the line's content (never the next line), and a `line` past the
+    /// last line gives `self.len()`.
     pub fn offset_of_position(&self, pos: &Position) -> usize {
         let (line, column) = self.position_to_line_col(pos);
 
         self.offset_of_line_col(line, column)
     }
 
-    pub fn position_to_line_col(&self, pos: &Position) -> (usize, usize) {
+    /// Like `offset_of_position`, but returns `None` if the position's `character` lands
+    /// between the two halves of a UTF16 surrogate pair, which is not a valid position.
+    pub fn try_offset_of_position(&self, pos: &Position) -> Option<usize> {
         let line = pos.line as usize;
         let line_offset = self.offset_of_line(line);
+        let (line_end, _) = self.line_content_end(line);
+        let character = pos.character as usize;
 
-        let column = offset_utf16_to_utf8(
-            self.char_indices_iter(line_offset..),
-            pos.character as usize,
-        );
+        let mut utf16_offset = 0;
+        for (_, c) in self.char_indices_iter(line_offset..line_end) {
+            if utf16_offset >= character {
+                break;
+            }
+
+            utf16_offset += c.len_utf16();
+            if utf16_offset > character {
+                // We skipped over the target, so it was inside of a surrogate pair
+                return None;
+            }
+        }
+
+        Some(self.offset_of_position(pos))
+    }
+
+    /// Converts a UTF16 LSP position to the line and UTF8 column.  
+    /// The scan stops at the end of the line's content, so a `character` past the end of the
+    /// line (even `u32::MAX`) is clamped to the end of the line rather than walking into the
+    /// lines after it.
+    pub fn position_to_line_col(&self, pos: &Position) -> (usize, usize) {
+        let line = pos.line as usize;
+        (line, self.utf16_col_to_col(line, pos.character as usize))
+    }
+
+    /// Convert a UTF16 column on the line to a UTF8 column, clamped to the end of the line's
+    /// content.
+    fn utf16_col_to_col(&self, line: usize, utf16_col: usize) -> usize {
+        let line_offset = self.offset_of_line(line);
+        let (line_end, _) = self.line_content_end(line);
 
-        (line, column)
+        offset_utf16_to_utf8(
+            self.char_indices_iter(line_offset..line_end),
+            utf16_col,
+        )
     }
 
+    /// Get the line and the byte column of the offset.  
+    /// The column is the number of bytes from the start of the line, not a count of
+    /// characters or graphemes.
+    /// An offset between the `\r` and `\n` of a CRLF line ending is treated as being before
+    /// the `\r`, so the column is never past the end of the line's content.
     pub fn offset_to_line_col(&self, offset: usize) -> (usize, usize) {
-        let offset = offset.min(self.len());
-        let line = self.line_of_offset(offset);
+        let offset = self.snap_out_of_crlf(offset.min(self.len()));
+        let (line, line_start) = self.line_and_start_of_offset(offset);
+        (line, offset - line_start)
+    }
+
+    /// Get the offset of the byte column on the line, the inverse of `offset_to_line_col`.  
+    /// A column in the middle of a character is snapped back to the start of that character,
+    /// and a column past the end of the line's content is clamped to the end of the content,
+    /// before the line ending, which is what `line_end_col` returns with `caret` set.
+    pub fn offset_of_line_col(&self, line: usize, col: usize) -> usize {
         let line_start = self.offset_of_line(line);
-        if offset == line_start {
-            return (line, 0);
+        let (line_end, _) = self.line_content_end(line);
+        for (pos, c) in self.char_indices_iter(line_start..line_end) {
+            if pos + c.len_utf8() > col {
+                return line_start + pos;
+            }
         }
 
-        let col = offset - line_start;
-        (line, col)
+        line_end
     }
 
-    pub fn offset_of_line_col(&self, line: usize, col: usize) -> usize {
-        let mut pos = 0;
-        let mut offset = self.offset_of_line(line);
-        for c in self
-            .slice_to_cow(offset..self.offset_of_line(line + 1))
-            .chars()
-        {
-            if c == '\n' {
-                return offset;
+    /// Get the offset for the `byte_col` on the line, like `offset_of_line_col`, but snapped
+    /// back to the start of the grapheme cluster it is within, along with the number of grapheme
+    /// clusters before it on the line (its grapheme column).
+    pub fn offset_and_grapheme_col_of_line_col(
+        &self,
+        line: usize,
+        byte_col: usize,
+    ) -> (usize, usize) {
+        let line_start = self.offset_of_line(line);
+        let target = self.offset_of_line_col(line, byte_col);
+
+        let mut cursor = Cursor::new(self.text, line_start);
+        let mut offset = line_start;
+        let mut grapheme_col = 0;
+        while let Some(next_offset) = cursor.next_grapheme() {
+            if next_offset > target {
+                break;
             }
+            offset = next_offset;
+            grapheme_col += 1;
+            cursor.set(next_offset);
+        }
+
+        (offset, grapheme_col)
+    }
+
+    /// Get the offset of the `grapheme_col`th grapheme cluster on the line, so that the
+    /// result is always on a cluster boundary, unlike `offset_of_line_col`.  
+    /// A column past the end of the line gives the end of its content, before the line
+    /// ending.
+    pub fn offset_of_line_grapheme_col(
+        &self,
+        line: usize,
+        grapheme_col: usize,
+    ) -> usize {
+        let line_start = self.offset_of_line(line);
+        let (line_end, _) = self.line_content_end(line);
 
-            let char_len = c.len_utf8();
-            if pos + char_len > col {
-                return offset;
+        let mut cursor = Cursor::new(self.text, line_start);
+        let mut offset = line_start;
+        for _ in 0..grapheme_col {
+            match cursor.next_grapheme() {
+                Some(next_offset) if next_offset <= line_end => {
+                    offset = next_offset;
+                    cursor.set(next_offset);
+                }
+                _ => break,
             }
-            pos += char_len;
-            offset += char_len;
         }
+
         offset
     }
 
@@ -140,30 +517,311 @@ pub fn line_end_col(&self, line: usize, caret: bool) -> usize {
     /// assert_eq!(text.line_end_offset(2, false), 11); // "world|"
     /// ```
     pub fn line_end_offset(&self, line: usize, caret: bool) -> usize {
-        let mut offset = self.offset_of_line(line + 1);
-        let mut line_content: &str = &self.line_content(line);
-        if line_content.ends_with("\r\n") {
-            offset -= 2;
-            line_content = &line_content[..line_content.len() - 2];
-        } else if line_content.ends_with('\n') {
-            offset -= 1;
-            line_content = &line_content[..line_content.len() - 1];
-        }
-        if !caret && !line_content.is_empty() {
-            offset = self.prev_grapheme_offset(offset, 1, 0);
+        let (offset, has_content) = self.line_content_end(line);
+        if !caret && has_content {
+            self.prev_grapheme_offset(offset, 1, 0)
+        } else {
+            offset
         }
-        offset
+    }
+
+    /// Get both the non-caret and caret end offsets of the line, as `line_end_offset` would
+    /// return them, in the form `(non_caret_end, caret_end)`.
+    pub fn line_ends(&self, line: usize) -> (usize, usize) {
+        let (caret_end, has_content) = self.line_content_end(line);
+        let non_caret_end = if has_content {
+            self.prev_grapheme_offset(caret_end, 1, 0)
+        } else {
+            caret_end
+        };
+        (non_caret_end, caret_end)
+    }
+
+    /// Get the offset of the end of the line's content, which is before the line ending,
+    /// and whether the line has any content at all.
+    fn line_content_end(&self, line: usize) -> (usize, bool) {
+        let line_content = self.line_content(line);
+        let ending_len = LineEnding::of_line(&line_content)
+            .map_or(0, |ending| ending.as_str().len());
+        let offset = self.offset_of_line(line.saturating_add(1)) - ending_len;
+        (offset, line_content.len() > ending_len)
     }
 
     /// Returns the content of the given line.
     /// Includes the line ending if it exists. (-> the last line won't have a line ending)    
     /// Lines past the end of the document will return an empty string.
     pub fn line_content(&self, line: usize) -> Cow<'a, str> {
+        let (start, end) = self.line_offset_bounds(line);
+        self.text.slice_to_cow(start..end)
+    }
+
+    /// Iterate over the line number and content (including the line ending) of every line
+    /// that intersects the given range. The line of the start of the range is always
+    /// included, even if the range is empty.  
+    /// This walks a single cursor over the lines, rather than looking up the offset of each
+    /// line, so it is cheaper than calling `line_content` for each line when painting a
+    /// viewport.
+    pub fn lines_in_range(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, Cow<'a, str>)> + 'a {
+        let text = self.text;
+        let Range { start, end } = self.clamp_range(range);
+
+        let mut line = self.line_of_offset(start);
+        let mut line_start = text.offset_of_line(line);
+        let mut cursor = Cursor::new(text, line_start);
+        let mut first = true;
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done || (!first && line_start >= end) {
+                return None;
+            }
+            first = false;
+
+            let next_line_start = cursor.next::<LinesMetric>();
+            let line_end = next_line_start.unwrap_or_else(|| text.len());
+            let item = (line, text.slice_to_cow(line_start..line_end));
+
+            match next_line_start {
+                Some(next_line_start) => {
+                    line += 1;
+                    line_start = next_line_start;
+                }
+                // The last line doesn't have a line ending
+                None => done = true,
+            }
+
+            Some(item)
+        })
+    }
+
+    /// Returns the content of the given line without its line ending, along with the line
+    /// ending itself (`"\r\n"`, `"\n"`, or `""` when the line has none, such as the last line).
+    pub fn line_content_split(&self, line: usize) -> (Cow<'a, str>, &'static str) {
+        let content = self.line_content(line);
+        let ending = LineEnding::of_line(&content).map_or("", |e| e.as_str());
+
+        let len = content.len() - ending.len();
+        let content = match content {
+            Cow::Borrowed(content) => Cow::Borrowed(&content[..len]),
+            Cow::Owned(mut content) => {
+                content.truncate(len);
+                Cow::Owned(content)
+            }
+        };
+
+        (content, ending)
+    }
+
+    /// Returns the content of the given line without its line ending, like
+    /// `line_content_split` without the ending. This only allocates when `line_content`
+    /// would.
+    pub fn line_content_trimmed(&self, line: usize) -> Cow<'a, str> {
+        self.line_content_split(line).0
+    }
+
+    /// Whether the line contains only whitespace, ignoring the line ending.  
+    /// Empty lines are considered blank.
+    pub fn is_line_blank(&self, line: usize) -> bool {
+        let content = self.line_content_trimmed(line);
+        content.chars().all(char::is_whitespace)
+    }
+
+    /// Get the range of lines in the paragraph around `line`, which are the consecutive
+    /// non-blank lines bounded by blank lines or the edges of the buffer.  
+    /// If `line` is blank, then the range is empty and starts at `line`. Lines past the end
+    /// are clamped to the last line.
+    pub fn paragraph_at(&self, line: usize) -> Range<usize> {
+        let line = line.min(self.last_line());
+        if self.is_line_blank(line) {
+            return line..line;
+        }
+
+        let mut start = line;
+        while start > 0 && !self.is_line_blank(start - 1) {
+            start -= 1;
+        }
+
+        let last_line = self.last_line();
+        let mut end = line + 1;
+        while end <= last_line && !self.is_line_blank(end) {
+            end += 1;
+        }
+
+        start..end
+    }
+
+    /// Whether the text between the offsets contains only whitespace, including line
+    /// endings, such as when deciding whether lines can be joined or collapsed.  
+    /// The range is clamped like `slice_to_cow`, and an empty range is blank.
+    pub fn is_blank_between(&self, start: usize, end: usize) -> bool {
+        self.char_indices_iter(start..end)
+            .all(|(_, c)| c.is_whitespace())
+    }
+
+    /// Get the range of the spaces and tabs at the end of the line, before the line ending.  
+    /// Returns `None` if the line has no trailing whitespace.
+    pub fn trailing_whitespace_range(&self, line: usize) -> Option<Range<usize>> {
+        let content = self.line_content_trimmed(line);
+        let trimmed_len = content.trim_end_matches([' ', '\t']).len();
+        if trimmed_len == content.len() {
+            return None;
+        }
+
+        let line_start = self.offset_of_line(line);
+        Some(line_start + trimmed_len..line_start + content.len())
+    }
+
+    /// Get the ranges of trailing whitespace on only the given lines, such as the lines
+    /// which were edited since the last save, so that trimming doesn't have to scan the
+    /// whole file.  
+    /// The ranges are ordered by offset and lines which are given more than once only
+    /// produce a single range.
+    pub fn trailing_whitespace_edits_for_lines(
+        &self,
+        lines: &[usize],
+    ) -> Vec<Range<usize>> {
+        let mut lines = lines.to_vec();
+        lines.sort_unstable();
+        lines.dedup();
+
+        lines
+            .into_iter()
+            .filter_map(|line| self.trailing_whitespace_range(line))
+            .collect()
+    }
+
+    /// Count the lines, without their line endings, for which `pred` returns true.  
+    /// This is a single forward pass over the lines, which are only allocated when they
+    /// cross a chunk boundary.
+    pub fn count_lines_matching(&self, pred: impl Fn(&str) -> bool) -> usize {
+        self.trimmed_lines_from(0)
+            .filter(|content| pred(content))
+            .count()
+    }
+
+    /// Get the first line at or after `start_line`, without its line ending, for which
+    /// `pred` returns true.
+    pub fn first_line_matching(
+        &self,
+        start_line: usize,
+        pred: impl Fn(&str) -> bool,
+    ) -> Option<usize> {
+        self.trimmed_lines_from(start_line)
+            .position(|content| pred(&content))
+            .map(|i| start_line + i)
+    }
+
+    /// Get the changes to the lines of this text which turn it into `other`, in order.  
+    /// Lines are compared without their line endings, so changing only the line ending of a
+    /// line is not a change. This uses Myers' diff in linear space, so it takes time
+    /// proportional to the number of lines times the number of changed lines, and memory
+    /// proportional to the number of lines.
+    pub fn line_diff(&self, other: &RopeText) -> Vec<LineChange> {
+        fn lines<'b>(text: &RopeText<'b>) -> Vec<Cow<'b, str>> {
+            // `lines_in_range` doesn't give the empty line after a trailing newline
+            text.lines_in_range(0..text.len())
+                .map(|(_, content)| content)
+                .chain(std::iter::repeat(Cow::Borrowed("")))
+                .take(text.num_lines())
+                .collect()
+        }
+        let old_lines = lines(self);
+        let new_lines = lines(other);
+        let old: Vec<&str> = old_lines.iter().map(|l| trim_line_ending(l)).collect();
+        let new: Vec<&str> = new_lines.iter().map(|l| trim_line_ending(l)).collect();
+
+        let mut kept = Vec::new();
+        diff_kept_lines(&old, &new, 0, 0, &mut kept);
+
+        // Every gap between the kept lines is a change
+        let mut changes = Vec::new();
+        let (mut old_line, mut new_line) = (0, 0);
+        for (kept_old, kept_new) in kept.into_iter().chain([(old.len(), new.len())])
+        {
+            if old_line < kept_old || new_line < kept_new {
+                changes
+                    .push(LineChange::new(old_line..kept_old, new_line..kept_new));
+            }
+            old_line = kept_old + 1;
+            new_line = kept_new + 1;
+        }
+
+        changes
+    }
+
+    /// Get the content of each of the lines, without their line endings, as owned strings,
+    /// such as for handing a snapshot of the lines to something that wants a `Vec<String>`.  
+    /// This intentionally allocates a `String` per line. Lines past the end are not included.
+    pub fn collect_lines(&self, lines: Range<usize>) -> Vec<String> {
+        let end = lines.end.min(self.num_lines());
+        if lines.start >= end {
+            return Vec::new();
+        }
+
+        let range = self.offset_of_line(lines.start)..self.offset_of_line(end);
+        let mut collected: Vec<String> = self
+            .lines_in_range(range)
+            .map(|(_, content)| {
+                let mut content = content.into_owned();
+                content.truncate(trim_line_ending(&content).len());
+                content
+            })
+            .collect();
+        // The empty last line after a trailing newline starts at the end of the text, so
+        // `lines_in_range` only gives it when the range starts there
+        collected.resize(end - lines.start, String::new());
+
+        collected
+    }
+
+    /// Iterate over the contents of the lines starting at `line`, without their line endings.  
+    /// This includes the empty last line after a trailing newline (unless this was created
+    /// with `without_trailing_empty_line`), which the rope's `lines` does not produce.
+    fn trimmed_lines_from(
+        &self,
+        line: usize,
+    ) -> impl Iterator<Item = Cow<'a, str>> + 'a {
+        let num_lines = self.num_lines().saturating_sub(line);
         self.text
-            .slice_to_cow(self.offset_of_line(line)..self.offset_of_line(line + 1))
+            .lines(self.offset_of_line(line)..)
+            .chain(std::iter::repeat(Cow::Borrowed("")))
+            .take(num_lines)
+    }
+
+    /// Get the line ending of the given line, if it has one.
+    pub fn line_ending_at(&self, line: usize) -> Option<LineEnding> {
+        LineEnding::of_line(&self.line_content(line))
+    }
+
+    /// Detect the line ending style used by the document, based on which is the most
+    /// common within the first thousand lines.  
+    /// Returns `None` if none of those lines have a line ending.
+    pub fn detect_line_ending(&self) -> Option<LineEnding> {
+        let mut lf = 0;
+        let mut crlf = 0;
+        for line in self.text.lines_raw(..).take(1000) {
+            match LineEnding::of_line(&line) {
+                Some(LineEnding::Lf) => lf += 1,
+                Some(LineEnding::CrLf) => crlf += 1,
+                None => {}
+            }
+        }
+
+        if lf == 0 && crlf == 0 {
+            None
+        } else if crlf > lf {
+            Some(LineEnding::CrLf)
+        } else {
+            Some(LineEnding::Lf)
+        }
     }
 
-    /// Get the offset of the previous grapheme cluster.
+    /// Get the offset of the previous grapheme cluster.  
+    /// This stops before going past `limit`, so it never returns an offset before `limit`
+    /// (unless `offset` already was). If `limit` is in the middle of a grapheme cluster, then
+    /// it stops at the end of that cluster, which is the first boundary after `limit`.
     pub fn prev_grapheme_offset(
         &self,
         offset: usize,
@@ -187,158 +845,3597 @@ pub fn prev_grapheme_offset(
         new_offset
     }
 
-    /// Returns the offset of the first non-blank character on the given line.  
-    /// If the line is one past the last line, then the offset at the end of the rope is returned.
-    /// If the line is further past that, then it defaults to the last line.
-    pub fn first_non_blank_character_on_line(&self, line: usize) -> usize {
-        let last_line = self.last_line();
-        let line = if line > last_line + 1 {
-            last_line
-        } else {
-            line
-        };
-        let line_start_offset = self.text.offset_of_line(line);
-        WordCursor::new(self.text, line_start_offset).next_non_blank_char()
+    /// Get the offset of the next grapheme cluster.  
+    /// This stops before going past `limit`. If the offset is in the middle of a grapheme
+    /// cluster, then the first step goes to the end of that cluster.
+    pub fn next_grapheme_offset(
+        &self,
+        offset: usize,
+        count: usize,
+        limit: usize,
+    ) -> usize {
+        let offset = offset.min(self.len());
+        let mut cursor = Cursor::new(self.text, offset);
+        let mut new_offset = offset;
+        for _i in 0..count {
+            if let Some(next_offset) = cursor.next_grapheme() {
+                if next_offset > limit {
+                    return new_offset;
+                }
+                new_offset = next_offset;
+                cursor.set(next_offset);
+            } else {
+                return new_offset;
+            }
+        }
+        new_offset
     }
 
-    pub fn indent_on_line(&self, line: usize) -> String {
-        let line_start_offset = self.text.offset_of_line(line);
-        let word_boundary =
-            WordCursor::new(self.text, line_start_offset).next_non_blank_char();
-        let indent = self.text.slice_to_cow(line_start_offset..word_boundary);
-        indent.to_string()
+    /// Get the character at the offset. If the offset is in the middle of a codepoint, then
+    /// the character containing it is returned.  
+    /// Returns `None` if the offset is at or past the end of the text.
+    pub fn char_at(&self, offset: usize) -> Option<char> {
+        if offset >= self.len() {
+            return None;
+        }
+
+        let offset = self
+            .text
+            .at_or_prev_codepoint_boundary(offset)
+            .unwrap_or(offset);
+        self.char_indices_iter(offset..).next().map(|(_, c)| c)
     }
 
-    /// Get the content of the rope as a Cow string, for 'nice' ranges (small, and at the right
-    /// offsets) this will be a reference to the rope's data. Otherwise, it allocates a new string.
-    /// You should be somewhat wary of requesting large parts of the rope, as it will allocate
+    /// Get the characters before and after the offset, as `(char_before, char_after)`.  
+    /// If the offset is in the middle of a codepoint, then it is snapped back to the start
+    /// of that codepoint.
+    pub fn chars_around(&self, offset: usize) -> (Option<char>, Option<char>) {
+        let offset = offset.min(self.len());
+        let offset = self
+            .text
+            .at_or_prev_codepoint_boundary(offset)
+            .unwrap_or(offset);
+
+        let before = self
+            .text
+            .prev_codepoint_offset(offset)
+            .and_then(|prev| self.char_at(prev));
+        (before, self.char_at(offset))
+    }
+
+    /// Get the full grapheme cluster containing the offset, such as a character along with
+    /// its combining marks.  
+    /// Returns `None` if the offset is at or past the end of the text.
+    pub fn grapheme_at(&self, offset: usize) -> Option<Cow<'a, str>> {
+        if offset >= self.len() {
+            return None;
+        }
+
+        let end = self.next_grapheme_offset(offset, 1, self.len());
+        let start = self.prev_grapheme_offset(end, 1, 0);
+        Some(self.slice_to_cow(start..end))
+    }
+
+    /// Iterate over the grapheme clusters in the range along with the offset of their start,
+    /// like `char_indices_iter` but keeping emoji sequences and combining marks together.  
+    /// The range is clamped like `slice_to_cow`, and should start on a cluster boundary. A
+    /// cluster which only partially overlaps the end of the range is still yielded in full.
+    /// Unlike `char_indices_iter`, the offsets are offsets into the rope.
+    pub fn graphemes_iter(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, Cow<'a, str>)> + 'a {
+        let Range { start, end } = self.clamp_range(range);
+        let text = self.text;
+        let mut cursor = Cursor::new(text, start);
+        std::iter::from_fn(move || {
+            let offset = cursor.pos();
+            if offset >= end {
+                return None;
+            }
+
+            let next = cursor.next_grapheme()?;
+            cursor.set(next);
+            Some((offset, text.slice_to_cow(offset..next)))
+        })
+    }
+
+    /// Get the offset of the bracket matching the one at `offset`, scanning forward for an
+    /// opening bracket and backward for a closing one while skipping over nested pairs.  
+    /// This is raw matching on the text, so brackets in strings and comments are counted too.
+    /// Returns `None` if there is no bracket at the offset, or if it is unbalanced.
+    pub fn matching_bracket_offset(&self, offset: usize) -> Option<usize> {
+        let c = self.char_at(offset)?;
+        let other = matching_char(c)?;
+        if !matching_pair_direction(c)? {
+            return WordCursor::new(self.text, offset).previous_unmatched(other);
+        }
+
+        let start = offset + c.len_utf8();
+        let mut depth = 0usize;
+        for (i, current) in self.char_indices_iter(start..) {
+            if current == c {
+                depth += 1;
+            } else if current == other {
+                if depth == 0 {
+                    return Some(start + i);
+                }
+                depth -= 1;
+            }
+        }
+
+        None
+    }
+
+    /// Returns the offset of the first non-blank character on the given line.  
+    /// If the line is one past the last line, then the offset at the end of the rope is returned.
+    /// If the line is further past that, then it defaults to the last line.
+    pub fn first_non_blank_character_on_line(&self, line: usize) -> usize {
+        let last_line = self.last_line();
+        let line = if line > last_line + 1 {
+            last_line
+        } else {
+            line
+        };
+        let line_start_offset = self.text.offset_of_line(line);
+        WordCursor::new(self.text, line_start_offset).next_non_blank_char()
+    }
+
+    /// Returns the offset just after the last non-blank character on the given line, before
+    /// any trailing whitespace and the line ending.  
+    /// If the line is entirely blank, then the offset of the start of the line is returned.
+    /// Out of bounds lines are clamped the same way as `first_non_blank_character_on_line`.
+    pub fn last_non_blank_character_on_line(&self, line: usize) -> usize {
+        let last_line = self.last_line();
+        let line = if line > last_line + 1 {
+            last_line
+        } else {
+            line
+        };
+        let line_start_offset = self.offset_of_line(line);
+        let (line_end, _) = self.line_content_end(line);
+        self.char_indices_rev_iter(line_start_offset..line_end)
+            .find(|(_, c)| get_char_property(*c) != CharClassification::Space)
+            .map_or(line_start_offset, |(i, c)| {
+                line_start_offset + i + c.len_utf8()
+            })
+    }
+
+    /// Get the range of the line's content without its leading and trailing whitespace, or
+    /// the line ending.  
+    /// Blank lines give an empty range at the start of the line.
+    pub fn line_content_span(&self, line: usize) -> Range<usize> {
+        let start = self.first_non_blank_character_on_line(line);
+        let end = self.last_non_blank_character_on_line(line);
+        if start < end {
+            start..end
+        } else {
+            end..end
+        }
+    }
+
+    /// Get the range of the word around the offset, using the same notion of a word as
+    /// `WordCursor::select_word`. An offset in the middle of a character is treated as being
+    /// at the start of it.  
+    /// If the character at the offset is whitespace, then an empty range at the offset is
+    /// returned, even if the offset is just after the end of a word. At the end of the text
+    /// the word before it is returned.
+    pub fn word_at_offset(&self, offset: usize) -> Range<usize> {
+        let offset = offset.min(self.len());
+        let offset = self
+            .text
+            .at_or_prev_codepoint_boundary(offset)
+            .unwrap_or(offset);
+        if let Some((_, c)) = self.char_indices_iter(offset..).next() {
+            if c.is_whitespace() {
+                return offset..offset;
+            }
+        }
+
+        let (start, end) = WordCursor::new(self.text, offset).select_word();
+        start..end
+    }
+
+    /// Get the `(start, end)` offsets of the word around the offset, like `word_at_offset`,
+    /// but returns `None` if there is no word there (such as on whitespace), so that callers
+    /// like double-click selection can tell that case apart.
+    pub fn word_boundaries(&self, offset: usize) -> Option<(usize, usize)> {
+        let range = self.word_at_offset(offset);
+        if range.is_empty() {
+            None
+        } else {
+            Some((range.start, range.end))
+        }
+    }
+
+    /// Find the nth (`count`) word starting at `offset` in either direction
+    /// depending on `find_next`.
+    ///
+    /// A `WordCursor` is created and given to the `find_next` function for the
+    /// search.  The `find_next` function should return None when there is no
+    /// more word found.  Despite the name, `find_next` can search in either
+    /// direction.
+    pub(crate) fn find_nth_word<F>(
+        &self,
+        offset: usize,
+        mut count: usize,
+        mut find_next: F,
+    ) -> usize
+    where
+        F: FnMut(&mut WordCursor) -> Option<usize>,
+    {
+        let mut cursor = WordCursor::new(self.text, offset);
+        let mut new_offset = offset;
+        while count != 0 {
+            // FIXME: wait for if-let-chain
+            if let Some(offset) = find_next(&mut cursor) {
+                new_offset = offset;
+            } else {
+                break;
+            }
+            count -= 1;
+        }
+        new_offset
+    }
+
+    /// Get the offset of the start of the `count`th word after the offset, using the word
+    /// boundaries of `WordCursor::next_boundary`. This stops at the end of the text.
+    pub fn move_n_words_forward(&self, offset: usize, count: usize) -> usize {
+        self.find_nth_word(offset, count, |cursor| cursor.next_boundary())
+    }
+
+    /// Get the offset of the start of the `count`th word before the offset, using the word
+    /// boundaries of `WordCursor::prev_boundary`. This stops at the start of the text.
+    pub fn move_n_words_backward(
+        &self,
+        offset: usize,
+        count: usize,
+        mode: Mode,
+    ) -> usize {
+        self.find_nth_word(offset, count, |cursor| cursor.prev_boundary(mode))
+    }
+
+    pub fn indent_on_line(&self, line: usize) -> String {
+        let line_start_offset = self.offset_of_line(line);
+        let word_boundary =
+            WordCursor::new(self.text, line_start_offset).next_non_blank_char();
+        let indent = self.text.slice_to_cow(line_start_offset..word_boundary);
+        indent.to_string()
+    }
+
+    /// Get the indentation of the line, like `indent_on_line`, along with its visual width
+    /// (with tabs extending to the next multiple of `tab_width`) and whether it uses tabs
+    /// and/or spaces.
+    pub fn indent_info_of_line(&self, line: usize, tab_width: usize) -> IndentInfo {
+        let tab_width = tab_width.max(1);
+        let text = self.indent_on_line(line);
+
+        let mut width = 0;
+        let mut uses_tabs = false;
+        let mut uses_spaces = false;
+        for c in text.chars() {
+            if c == '\t' {
+                uses_tabs = true;
+                width += tab_width - width % tab_width;
+            } else {
+                uses_spaces = true;
+                width += 1;
+            }
+        }
+
+        IndentInfo {
+            text,
+            width,
+            uses_tabs,
+            uses_spaces,
+        }
+    }
+
+    /// Get the content of the rope as a Cow string, for 'nice' ranges (small, and at the right
+    /// offsets) this will be a reference to the rope's data. Otherwise, it allocates a new string.
+    /// You should be somewhat wary of requesting large parts of the rope, as it will allocate
     /// a new string since it isn't contiguous in memory for large chunks.
     pub fn slice_to_cow(&self, range: Range<usize>) -> Cow<'a, str> {
-        self.text
-            .slice_to_cow(range.start.min(self.len())..range.end.min(self.len()))
+        self.text.slice_to_cow(self.clamp_range(range))
     }
 
-    /// Iterate over (utf8_offset, char) values in the given range  
-    /// This uses `iter_chunks` and so does not allocate, compared to `slice_to_cow` which can
-    pub fn char_indices_iter<T: IntervalBounds>(
+    /// Write the content of the range to `out` a chunk at a time, like `slice_to_cow`, but
+    /// without allocating a string for large ranges.
+    pub fn write_range(
         &self,
-        range: T,
-    ) -> impl Iterator<Item = (usize, char)> + 'a {
-        CharIndicesJoin::new(self.text.iter_chunks(range).map(str::char_indices))
+        range: Range<usize>,
+        out: &mut impl std::fmt::Write,
+    ) -> std::fmt::Result {
+        for chunk in self.text.iter_chunks(self.clamp_range(range)) {
+            out.write_str(chunk)?;
+        }
+
+        Ok(())
     }
 
-    /// The number of lines in the file
-    pub fn num_lines(&self) -> usize {
-        self.last_line() + 1
+    /// Clamp the range to the bounds of the text.  
+    /// An inverted range (where the start is after the end), such as from a selection made
+    /// by dragging backwards, becomes an empty range at its clamped end.
+    fn clamp_range(&self, range: impl RangeBounds<usize>) -> Range<usize> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.saturating_add(1),
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.len(),
+        };
+
+        let end = end.min(self.len());
+        let start = start.min(end);
+        start..end
     }
 
-    /// The length of the given line
-    pub fn line_len(&self, line: usize) -> usize {
-        self.offset_of_line(line + 1) - self.offset_of_line(line)
+    /// Iterate over the raw bytes in the range, clamped like `slice_to_cow`, without building
+    /// a string. Unlike `slice_to_cow`, the range may start or end in the middle of a codepoint.
+    pub fn bytes_in_range(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = u8> + 'a {
+        let Range { start, end } = self.clamp_range(range);
+
+        // The chunks can only be split on codepoint boundaries, so we widen the range to them
+        // and then skip the extra bytes
+        let chunks_start = self
+            .text
+            .at_or_prev_codepoint_boundary(start)
+            .unwrap_or(start);
+        let chunks_end = self.at_or_next_codepoint_boundary(end);
+        self.text
+            .iter_chunks(chunks_start..chunks_end)
+            .flat_map(str::bytes)
+            .skip(start - chunks_start)
+            .take(end - start)
     }
-}
 
-/// Joins an iterator of iterators over char indices `(usize, char)` into one
-/// as if they were from a single long string
-/// Assumes the iterators end after the first `None` value
-#[derive(Clone)]
-pub struct CharIndicesJoin<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>>
-{
-    /// Our iterator of iterators
-    main_iter: O,
-    /// Our current working iterator of indices
-    current_indices: Option<I>,
-    /// The amount we should shift future offsets
-    current_base: usize,
-    /// The latest base, since we don't know when the `current_indices` iterator will end
-    latest_base: usize,
-}
+    /// Get the byte at the offset, or `None` if it is at or past the end of the text.
+    pub fn byte_at(&self, offset: usize) -> Option<u8> {
+        if offset < self.len() {
+            Some(self.text.byte_at(offset))
+        } else {
+            None
+        }
+    }
 
-impl<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>>
-    CharIndicesJoin<I, O>
-{
-    pub fn new(main_iter: O) -> CharIndicesJoin<I, O> {
-        CharIndicesJoin {
-            main_iter,
-            current_indices: None,
-            current_base: 0,
-            latest_base: 0,
+    /// Whether the range only contains ASCII, stopping at the first chunk that doesn't.  
+    /// An empty range is ASCII.
+    pub fn range_is_ascii(&self, range: Range<usize>) -> bool {
+        self.text
+            .iter_chunks(self.clamp_range(range))
+            .all(|chunk| chunk.is_ascii())
+    }
+
+    /// Whether the range contains the character, without allocating the range as a string.
+    pub fn range_contains_char(&self, range: Range<usize>, needle: char) -> bool {
+        self.text
+            .iter_chunks(self.clamp_range(range))
+            .any(|chunk| chunk.contains(needle))
+    }
+
+    /// Hash the bytes of the range, for cheaply checking whether a region changed between
+    /// snapshots. The range is clamped like `slice_to_cow`.  
+    /// This is 64 bit FNV-1a over the bytes, fed a chunk at a time, so equal content gives
+    /// an equal hash regardless of how the rope is split into chunks, and the value is the
+    /// same between runs. It is not resistant to deliberate collisions.
+    pub fn range_hash(&self, range: Range<usize>) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        self.text
+            .iter_chunks(self.clamp_range(range))
+            .flat_map(str::bytes)
+            .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// Whether the range of this text has the same bytes as the range of the other text,
+    /// such as for checking whether a line actually changed between two versions. The ranges
+    /// are clamped like `slice_to_cow`.  
+    /// This compares the chunks of both ropes as they line up, without allocating, and stops
+    /// at the first difference. Ranges of different lengths are never equal.
+    pub fn eq_range(
+        &self,
+        other: &RopeText,
+        self_range: Range<usize>,
+        other_range: Range<usize>,
+    ) -> bool {
+        let self_range = self.clamp_range(self_range);
+        let other_range = other.clamp_range(other_range);
+        if self_range.len() != other_range.len() {
+            return false;
+        }
+
+        let mut self_chunks = self.text.iter_chunks(self_range).map(str::as_bytes);
+        let mut other_chunks =
+            other.text.iter_chunks(other_range).map(str::as_bytes);
+        let mut self_chunk: &[u8] = &[];
+        let mut other_chunk: &[u8] = &[];
+        loop {
+            if self_chunk.is_empty() {
+                match self_chunks.next() {
+                    Some(chunk) => self_chunk = chunk,
+                    // The lengths are the same, so both have run out
+                    None => return true,
+                }
+            }
+            if other_chunk.is_empty() {
+                match other_chunks.next() {
+                    Some(chunk) => other_chunk = chunk,
+                    None => return true,
+                }
+            }
+
+            let len = self_chunk.len().min(other_chunk.len());
+            if self_chunk[..len] != other_chunk[..len] {
+                return false;
+            }
+            self_chunk = &self_chunk[len..];
+            other_chunk = &other_chunk[len..];
         }
     }
-}
 
-impl<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>> Iterator
-    for CharIndicesJoin<I, O>
-{
-    type Item = (usize, char);
+    /// Like `slice_to_cow`, but also returns whether the slice had to be allocated (true) or
+    /// is borrowed from the rope (false), for callers deciding whether it is worth caching.
+    pub fn slice_with_kind(&self, range: Range<usize>) -> (Cow<'a, str>, bool) {
+        let slice = self.slice_to_cow(range);
+        let owned = matches!(slice, Cow::Owned(_));
+        (slice, owned)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(current) = &mut self.current_indices {
-            if let Some((next_offset, next_ch)) = current.next() {
-                // Shift by the current base offset, which is the accumulated offset from previous
-                // iterators, which makes so the offset produced looks like it is from one long str
-                let next_offset = self.current_base + next_offset;
-                // Store the latest base offset, because we don't know when the current iterator
-                // will end (though technically the str iterator impl does)
-                self.latest_base = next_offset + next_ch.len_utf8();
-                return Some((next_offset, next_ch));
+    /// Like `slice_to_cow`, but only returns up to `max_bytes` of the range, cut at the
+    /// previous codepoint boundary so that it is still valid UTF8.  
+    /// The bool is true if the returned text was truncated.  
+    /// This is useful for things like previews, where we don't want to accidentally allocate
+    /// a huge string just to show the start of it.
+    pub fn slice_capped(
+        &self,
+        range: Range<usize>,
+        max_bytes: usize,
+    ) -> (Cow<'a, str>, bool) {
+        let Range { start, end } = self.clamp_range(range);
+        if end - start <= max_bytes {
+            return (self.slice_to_cow(start..end), false);
+        }
+
+        let end = self
+            .text
+            .at_or_prev_codepoint_boundary(start + max_bytes)
+            .unwrap_or(start)
+            .max(start);
+        (self.slice_to_cow(start..end), true)
+    }
+
+    /// Find the first occurrence of `needle` at or after the `start` offset, returning the
+    /// offset of the start of the match.
+    pub fn find(&self, start: usize, needle: &str) -> Option<usize> {
+        let start = self.at_or_next_codepoint_boundary(start);
+
+        let mut cursor = Cursor::new(self.text, start);
+        let mut lines = self.text.lines_raw(start..self.len());
+        find(&mut cursor, &mut lines, CaseMatching::Exact, needle, None)
+    }
+
+    /// Iterate over the offsets of every occurrence of `needle`, including those which
+    /// overlap. After each match the search continues from the next codepoint, rather than
+    /// from after the match, so `"aa"` is found 3 times in `"aaaa"`.
+    pub fn matches_overlapping<'n>(
+        &self,
+        needle: &'n str,
+    ) -> impl Iterator<Item = usize> + 'n
+    where
+        'a: 'n,
+    {
+        let text = self.text;
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let offset = RopeText::new(text).find(start, needle)?;
+            // `find` snaps this forward to the start of the next codepoint
+            start = offset + 1;
+            Some(offset)
+        })
+    }
+
+    /// Iterate over the byte ranges of the matches of `re` within the range, in order.  
+    /// The range is clamped like `slice_to_cow`, and the matches are found over
+    /// `slice_to_cow` of it, so large ranges allocate. Each match is only searched for when
+    /// the iterator is advanced, and empty matches are handled like `Regex::find_iter`.  
+    /// The regex is cloned (which is cheap) so that the iterator doesn't borrow it.  
+    /// The match ranges are offsets into the rope, not into the range.
+    pub fn matches_iter(
+        &self,
+        re: &Regex,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = Range<usize>> + 'a {
+        let re = re.clone();
+        let range = self.clamp_range(range);
+        let start = range.start;
+        let content = self.slice_to_cow(range);
+
+        // Where to search from next, and the end of the last match
+        let mut search_start = 0;
+        let mut last_match_end = None;
+        std::iter::from_fn(move || loop {
+            if search_start > content.len() {
+                return None;
             }
+
+            let m = re.find_at(&content, search_start)?;
+            if m.start() == m.end() {
+                // Step over the next character so that we don't keep finding the same
+                // empty match
+                search_start = m.end()
+                    + content[m.end()..].chars().next().map_or(1, char::len_utf8);
+                if last_match_end == Some(m.end()) {
+                    continue;
+                }
+            } else {
+                search_start = m.end();
+            }
+            last_match_end = Some(m.end());
+
+            return Some(start + m.start()..start + m.end());
+        })
+    }
+
+    /// Find the first occurrence of `needle` at or after the `start` offset, returning the
+    /// offset of the start of the match along with its UTF16 LSP position.  
+    /// This finds the line of `start` once and then walks the lines forward while searching,
+    /// keeping count of the line, so the position doesn't need another lookup in the rope.
+    /// The result is the same as `find` and then `offset_to_position`.
+    pub fn find_with_position(
+        &self,
+        start: usize,
+        needle: &str,
+    ) -> Option<(usize, Position)> {
+        if needle.is_empty() {
+            return None;
         }
 
-        // Otherwise, if we didn't return something above, then we get a next iterator
-        if let Some(next_current) = self.main_iter.next() {
-            // Update our current working iterator
-            self.current_indices = Some(next_current);
-            // Update the current base offset with the previous iterators latest offset base
-            // This is what we are shifting by
-            self.current_base = self.latest_base;
+        let start = self.at_or_next_codepoint_boundary(start);
+        let (mut line, mut line_start) = self.line_and_start_of_offset(start);
+
+        // A match spans one more line than the number of line breaks in the needle, so we
+        // search a window of that many lines for a match that starts in the first of them
+        let window_len = needle.matches('\n').count() + 1;
+        let mut lines = self.text.lines_raw(line_start..self.len());
+        let mut window: VecDeque<Cow<'a, str>> =
+            lines.by_ref().take(window_len).collect();
+        while let Some(first) = window.front() {
+            let from = start.saturating_sub(line_start);
+            let found = if window_len == 1 {
+                first[from..].find(needle)
+            } else {
+                let joined: String = window.iter().map(|l| l.as_ref()).collect();
+                joined[from..].find(needle)
+            };
+
+            if let Some(col) = found.map(|col| from + col) {
+                if col < first.len() {
+                    // Match `offset_to_position` for a match starting within a CRLF
+                    let position_col = if first[..col].ends_with('\r')
+                        && first[col..].starts_with('\n')
+                    {
+                        col - 1
+                    } else {
+                        col
+                    };
+                    let position = Position {
+                        line: line as u32,
+                        character: first[..position_col].encode_utf16().count()
+                            as u32,
+                    };
+                    return Some((line_start + col, position));
+                }
+            }
+
+            line += 1;
+            line_start += first.len();
+            window.pop_front();
+            window.extend(lines.next());
+        }
+
+        None
+    }
+
+    /// Find the first occurrence of `needle` on the line at or after the byte column
+    /// `from_col`, returning the offset of the start of the match.  
+    /// Only the line is searched (including its line ending), so this is cheap for
+    /// highlighting matches as the user types. A `from_col` in the middle of a character
+    /// starts from the next character.
+    pub fn find_in_line(
+        &self,
+        line: usize,
+        needle: &str,
+        from_col: usize,
+    ) -> Option<usize> {
+        let content = self.line_content(line);
+        let from_col = next_char_boundary(&content, from_col)?;
+        let col = from_col + content[from_col..].find(needle)?;
+        Some(self.offset_of_line(line) + col)
+    }
+
+    /// Like `find_in_line`, but ASCII letters match regardless of case.
+    pub fn find_in_line_ignore_ascii_case(
+        &self,
+        line: usize,
+        needle: &str,
+        from_col: usize,
+    ) -> Option<usize> {
+        let content = self.line_content(line);
+        let from_col = next_char_boundary(&content, from_col)?;
+        let needle = needle.as_bytes();
+        let last_col = content.len().checked_sub(needle.len())?;
+        let col = (from_col..=last_col).find(|&col| {
+            content.is_char_boundary(col)
+                && content.as_bytes()[col..col + needle.len()]
+                    .eq_ignore_ascii_case(needle)
+        })?;
+        Some(self.offset_of_line(line) + col)
+    }
+
+    /// Find the first occurrence of `needle` at or after the `start` offset, returning the
+    /// offset of the match along with a snippet of up to `context` grapheme clusters on each
+    /// side of it, for previewing the match in a results list.  
+    /// The context does not extend past the lines of the match, or into the line ending.
+    /// The range is the byte range of the match within the snippet, for highlighting it.
+    pub fn find_with_context(
+        &self,
+        start: usize,
+        needle: &str,
+        context: usize,
+    ) -> Option<(usize, String, Range<usize>)> {
+        let offset = self.find(start, needle)?;
+        let match_end = offset + needle.len();
+
+        let line_start = self.offset_of_line(self.line_of_offset(offset));
+        let (line_end, _) = self.line_content_end(self.line_of_offset(match_end));
+        let snippet_start = self.prev_grapheme_offset(offset, context, line_start);
+        let snippet_end =
+            self.next_grapheme_offset(match_end, context, line_end.max(match_end));
+
+        let snippet = self.slice_to_cow(snippet_start..snippet_end).into_owned();
+        let highlight = offset - snippet_start..match_end - snippet_start;
+        Some((offset, snippet, highlight))
+    }
+
+    /// Iterate over (utf8_offset, char) values in the given range  
+    /// This uses `iter_chunks` and so does not allocate, compared to `slice_to_cow` which can
+    /// When the range is within a single chunk of the rope, this iterates over that chunk
+    /// directly, skipping the overhead of joining the chunks.
+    pub fn char_indices_iter<T: RangeBounds<usize>>(
+        &self,
+        range: T,
+    ) -> impl Iterator<Item = (usize, char)> + 'a {
+        let Range { start, end } = self.clamp_range(range);
+        if let Some(chunk) = self.single_chunk(start, end) {
+            return Either::Left(chunk.char_indices());
+        }
 
-            // Get the next item without new current iterator
-            // As long as main_iter and the iterators it produces aren't infinite then this
-            // recursion won't be infinite either
-            // and even the non-recursion version would be infinite if those were infinite
-            self.next()
+        Either::Right(CharIndicesJoin::new(
+            self.text.iter_chunks(start..end).map(str::char_indices),
+        ))
+    }
+
+    /// Iterate over at most `n` (utf8_offset, char) values from `start`, such as for checking
+    /// the start of the text for a shebang or BOM without guessing at how much to slice.  
+    /// The offsets are relative to `start`, like `char_indices_iter`.
+    pub fn chars_take(
+        &self,
+        start: usize,
+        n: usize,
+    ) -> impl Iterator<Item = (usize, char)> + 'a {
+        self.char_indices_iter(start..).take(n)
+    }
+
+    /// Get the text between `start` and `end` if it is entirely within one chunk of the rope.
+    fn single_chunk(&self, start: usize, end: usize) -> Option<&'a str> {
+        let len = end.checked_sub(start)?;
+        let (leaf, offset_in_leaf) = Cursor::new(self.text, start).get_leaf()?;
+        if offset_in_leaf + len <= leaf.len() {
+            Some(&leaf[offset_in_leaf..offset_in_leaf + len])
         } else {
-            // We didn't get anything from the main iter, so we're completely done.
             None
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use lapce_xi_rope::Rope;
+    /// Iterate over (utf8_offset, char) values in the given range, from the end of the range
+    /// towards the start.  
+    /// The offsets are relative to the start of the range, like `char_indices_iter`.
+    pub fn char_indices_rev_iter<T: RangeBounds<usize>>(
+        &self,
+        range: T,
+    ) -> impl Iterator<Item = (usize, char)> + 'a {
+        let Range { start, end } = self.clamp_range(range);
+        RevCharIndicesJoin::new(
+            self.iter_chunks_rev(start, end)
+                .map(|chunk| chunk.char_indices().rev()),
+            end - start,
+        )
+    }
+
+    /// Iterate over the chunks of the rope between `start` and `end`, in reverse order.
+    fn iter_chunks_rev(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        let mut cursor = Cursor::new(self.text, end);
+        // The current leaf, along with the offset within it that we have yet to go back from
+        let mut current = cursor.get_leaf();
+        let mut leaf_end = end;
+        std::iter::from_fn(move || loop {
+            if leaf_end <= start {
+                return None;
+            }
 
-    use super::RopeText;
+            let (leaf, offset_in_leaf) = current?;
+            let leaf_start = leaf_end - offset_in_leaf;
+            let chunk = &leaf[start.max(leaf_start) - leaf_start..offset_in_leaf];
+
+            current = cursor.prev_leaf().map(|(leaf, _)| (leaf, leaf.len()));
+            leaf_end = leaf_start;
+
+            // The end may be at the very start of a leaf, which gives us an empty chunk
+            if !chunk.is_empty() {
+                return Some(chunk);
+            }
+        })
+    }
+
+    /// Iterate over every `stride`-th (utf8_offset, char) value in the given range, starting
+    /// with the first character. The offsets are relative to the start of the range, like
+    /// `char_indices_iter`.  
+    /// A `stride` of 0 is treated as 1.
+    pub fn sample_chars<T: RangeBounds<usize>>(
+        &self,
+        range: T,
+        stride: usize,
+    ) -> impl Iterator<Item = (usize, char)> + 'a {
+        self.char_indices_iter(range).step_by(stride.max(1))
+    }
+
+    /// The number of lines in the file
+    pub fn num_lines(&self) -> usize {
+        self.last_line() + 1
+    }
+
+    /// The number of digits in the last line number (`num_lines`) when written in `base`,
+    /// which is how many columns the gutter needs for line numbers.  
+    /// The base must be at least 2. This is checked in debug builds, while release builds
+    /// treat a base of 0 or 1 as 2.
+    pub fn last_line_number_width(&self, base: u32) -> usize {
+        debug_assert!(base >= 2, "line number base must be at least 2, got {base}");
+        let base = base.max(2) as usize;
+        let mut line_number = self.num_lines();
+        let mut width = 1;
+        while line_number >= base {
+            line_number /= base;
+            width += 1;
+        }
+        width
+    }
+
+    /// The length of the given line
+    pub fn line_len(&self, line: usize) -> usize {
+        let (start, end) = self.line_offset_bounds(line);
+        end - start
+    }
+
+    /// The length of the given line, not including its line ending
+    pub fn line_len_without_ending(&self, line: usize) -> usize {
+        let (end, _) = self.line_content_end(line);
+        end - self.offset_of_line(line)
+    }
+
+    /// Get the start offset and display width of each grapheme cluster on the line, not
+    /// including the line ending.  
+    /// Tabs extend to the next multiple of `tab_width`, wide characters (such as CJK) take
+    /// up two columns, and combining marks add no width to the cluster they are part of.
+    /// ```rust,ignore
+    /// let text = Rope::from("a\tb");
+    /// let text = RopeText::new(&text);
+    /// let widths: Vec<_> = text.grapheme_widths(0, 4).collect();
+    /// assert_eq!(widths, vec![(0, 1), (1, 3), (2, 1)]);
+    /// ```
+    pub fn grapheme_widths(
+        &self,
+        line: usize,
+        tab_width: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let text = self.text;
+        let tab_width = tab_width.max(1);
+        let line_start = self.offset_of_line(line);
+        let (line_end, _) = self.line_content_end(line);
+
+        let mut cursor = Cursor::new(text, line_start);
+        let mut col = 0;
+        std::iter::from_fn(move || {
+            let start = cursor.pos();
+            if start >= line_end {
+                return None;
+            }
+
+            let end = cursor.next_grapheme()?.min(line_end);
+            cursor.set(end);
+
+            let grapheme = text.slice_to_cow(start..end);
+            let width = if grapheme == "\t" {
+                tab_width - col % tab_width
+            } else {
+                UnicodeWidthStr::width(grapheme.as_ref())
+            };
+            col += width;
+
+            Some((start, width))
+        })
+    }
+
+    /// The number of grapheme clusters on the line, not including the line ending.
+    pub fn line_grapheme_len(&self, line: usize) -> usize {
+        let line_start = self.offset_of_line(line);
+        let (line_end, _) = self.line_content_end(line);
+        self.grapheme_count(line_start..line_end)
+    }
+
+    /// The number of characters (Unicode scalar values) in the text, rather than its length
+    /// in bytes. This is not the number of grapheme clusters, see `grapheme_count` for that.
+    pub fn char_count(&self) -> usize {
+        self.char_count_in_range(0..self.len())
+    }
+
+    /// The number of characters (Unicode scalar values) in the range, clamped like
+    /// `slice_to_cow`.
+    pub fn char_count_in_range(&self, range: Range<usize>) -> usize {
+        self.text
+            .iter_chunks(self.clamp_range(range))
+            .map(|chunk| chunk.chars().count())
+            .sum()
+    }
+
+    /// The number of grapheme clusters in the range. A cluster which only partially overlaps
+    /// the end of the range is still counted.
+    pub fn grapheme_count(&self, range: Range<usize>) -> usize {
+        let Range { start, end } = self.clamp_range(range);
+
+        let mut cursor = Cursor::new(self.text, start);
+        let mut count = 0;
+        while cursor.pos() < end {
+            match cursor.next_grapheme() {
+                Some(next) => {
+                    count += 1;
+                    cursor.set(next);
+                }
+                None => break,
+            }
+        }
+
+        count
+    }
+
+    /// Get the visual column of the offset within its line, using the widths from
+    /// `grapheme_widths`.  
+    /// If the offset is in the middle of a grapheme cluster, then the column of the start
+    /// of that cluster is returned.
+    /// ```rust,ignore
+    /// let text = Rope::from("\tabc");
+    /// let text = RopeText::new(&text);
+    /// assert_eq!(text.visual_col_of_offset(1, 4), 4); // "\t|abc"
+    /// ```
+    pub fn visual_col_of_offset(&self, offset: usize, tab_width: usize) -> usize {
+        let line = self.line_of_offset(offset);
+        let (line_end, _) = self.line_content_end(line);
+        let offset = if offset < line_end {
+            // Snap back to the start of the grapheme cluster
+            let end = self.next_grapheme_offset(offset, 1, line_end);
+            self.prev_grapheme_offset(end, 1, 0)
+        } else {
+            line_end
+        };
+
+        self.grapheme_widths(line, tab_width)
+            .take_while(|(start, _)| *start < offset)
+            .map(|(_, width)| width)
+            .sum()
+    }
+
+    /// Get the offset on the line at the given visual column, which is the inverse of
+    /// `visual_col_of_offset`.  
+    /// If the column is in the middle of a tab or wide character, then the offset of it is
+    /// returned, and if it is past the end of the line, then the offset of the end of the
+    /// line is.
+    pub fn offset_of_visual_col(
+        &self,
+        line: usize,
+        visual_col: usize,
+        tab_width: usize,
+    ) -> usize {
+        self.grapheme_at_visual_col(line, visual_col, tab_width)
+            .map_or_else(|| self.line_content_end(line).0, |(offset, _)| offset)
+    }
+
+    /// Get the offset of the grapheme cluster which covers the visual column on the line,
+    /// along with the visual column that it starts at, such as to draw the caret at the
+    /// leading edge of a tab.  
+    /// Returns `None` if the column is past the end of the line.
+    /// ```rust,ignore
+    /// let text = Rope::from("\tabc");
+    /// let text = RopeText::new(&text);
+    /// assert_eq!(text.grapheme_at_visual_col(0, 2, 4), Some((0, 0))); // Inside the tab
+    /// assert_eq!(text.grapheme_at_visual_col(0, 4, 4), Some((1, 4))); // "\t|abc"
+    /// ```
+    pub fn grapheme_at_visual_col(
+        &self,
+        line: usize,
+        visual_col: usize,
+        tab_width: usize,
+    ) -> Option<(usize, usize)> {
+        let mut col = 0;
+        for (start, width) in self.grapheme_widths(line, tab_width) {
+            if col + width > visual_col {
+                return Some((start, col));
+            }
+            col += width;
+        }
+
+        None
+    }
+
+    /// Get the offsets at which the given line would be soft wrapped, if it was wrapped at
+    /// `width` columns. Each offset is the start of a new visual row, so a line that fits
+    /// returns no offsets.  
+    /// The widths of the grapheme clusters are those from `grapheme_widths`, so tabs extend
+    /// to the next multiple of `tab_width` within the whole line. A `width` of 0 disables
+    /// wrapping.
+    pub fn wrap_points(
+        &self,
+        line: usize,
+        width: usize,
+        tab_width: usize,
+    ) -> Vec<usize> {
+        self.wrap_points_iter(line, width, tab_width).collect()
+    }
+
+    /// The number of visual rows the given line takes up when soft wrapped at
+    /// `max_cols` columns, which is always at least 1.  
+    /// This uses the same column walk as `wrap_points`, so it is based on character
+    /// widths rather than font metrics.
+    pub fn wrapped_rows(
+        &self,
+        line: usize,
+        max_cols: usize,
+        tab_width: usize,
+    ) -> usize {
+        self.wrap_points_iter(line, max_cols, tab_width).count() + 1
+    }
+
+    fn wrap_points_iter(
+        &self,
+        line: usize,
+        width: usize,
+        tab_width: usize,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let mut col = 0;
+        self.grapheme_widths(line, tab_width)
+            .take_while(move |_| width > 0)
+            .filter_map(move |(offset, grapheme_width)| {
+                let wrap = col > 0 && col + grapheme_width > width;
+                col = if wrap { 0 } else { col } + grapheme_width;
+                wrap.then_some(offset)
+            })
+    }
+
+    /// Get a reader for looking up consecutive lines, which reuses its position in the
+    /// rope between queries rather than descending it for every line.
+    pub fn line_reader(&self) -> LineReader<'a> {
+        LineReader::new(self.text, self.last_line())
+    }
+
+    /// The number of visual lines in the file if it was soft wrapped at `width` columns.  
+    /// This walks every line of the document, so it is expensive for large files and the
+    /// result should be cached rather than recomputed on every paint.
+    pub fn visual_line_count(&self, width: usize, tab_width: usize) -> usize {
+        (0..self.num_lines())
+            .map(|line| self.wrapped_rows(line, width, tab_width))
+            .sum()
+    }
+}
+
+/// Get the content of a line without its line ending, if it has one.
+fn trim_line_ending(content: &str) -> &str {
+    let ending = LineEnding::of_line(content).map_or("", |e| e.as_str());
+    &content[..content.len() - ending.len()]
+}
+
+/// Get the first char boundary in `s` at or after `index`, if it isn't past the end.
+fn next_char_boundary(s: &str, index: usize) -> Option<usize> {
+    (index..=s.len()).find(|&index| s.is_char_boundary(index))
+}
+
+/// Push the pairs of lines of `old` and `new` which are kept by the shortest edit between
+/// them, in order, shifted by `old_start` and `new_start`.  
+/// This is Myers' diff in linear space: after skipping the common prefix and suffix, a
+/// point in the middle of the shortest edit is found by `middle_snake`, and then each side
+/// of it is diffed on its own.
+fn diff_kept_lines(
+    old: &[&str],
+    new: &[&str],
+    old_start: usize,
+    new_start: usize,
+    kept: &mut Vec<(usize, usize)>,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    kept.extend((0..prefix).map(|i| (old_start + i, new_start + i)));
+
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+    let (old_start, new_start) = (old_start + prefix, new_start + prefix);
+
+    // If either side is empty, then the rest is only insertions or removals
+    if !old.is_empty() && !new.is_empty() {
+        let (x, y) = middle_snake(old, new);
+        diff_kept_lines(&old[..x], &new[..y], old_start, new_start, kept);
+        diff_kept_lines(&old[x..], &new[y..], old_start + x, new_start + y, kept);
+    }
+
+    let (old_end, new_end) = (old_start + old.len(), new_start + new.len());
+    kept.extend((0..suffix).map(|i| (old_end + i, new_end + i)));
+}
+
+/// Find a point `(x, y)` which is on a shortest edit from `old` to `new`, by searching
+/// forwards from the start and backwards from the end until the searches overlap.  
+/// Both must be non-empty and not start or end with the same line, so that the point
+/// splits the edit into two smaller ones.
+fn middle_snake(old: &[&str], new: &[&str]) -> (usize, usize) {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max_d = (n + m + 1) / 2;
+
+    // The furthest x reached on each diagonal `k = x - y`, forwards from the start and
+    // backwards from the end, indexed from `-max_d - 1`
+    let index = |k: isize| (k + max_d + 1) as usize;
+    let mut forward = vec![0isize; index(max_d + 1) + 1];
+    let mut backward = vec![0isize; index(max_d + 1) + 1];
+
+    for d in 0..=max_d {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d
+                || (k != d && forward[index(k - 1)] < forward[index(k + 1)])
+            {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (start_x, start_y) = (x, y);
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+
+            let back_k = delta - k;
+            if odd
+                && (-(d - 1)..=d - 1).contains(&back_k)
+                && x + backward[index(back_k)] >= n
+            {
+                return (start_x as usize, start_y as usize);
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d
+                || (k != d && backward[index(k - 1)] < backward[index(k + 1)])
+            {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n
+                && y < m
+                && old[(n - x - 1) as usize] == new[(m - y - 1) as usize]
+            {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+
+            let forward_k = delta - k;
+            if !odd
+                && (-d..=d).contains(&forward_k)
+                && x + forward[index(forward_k)] >= n
+            {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+
+    // The searches always meet by `max_d`, but if they somehow don't, then removing all of
+    // `old` before inserting all of `new` is still a valid split
+    (old.len(), 0)
+}
+
+/// Looks up the lines of offsets, remembering the line of the last query so that queries
+/// for nearby offsets (such as when scrolling) only have to step over a few lines rather
+/// than descending the rope each time.  
+/// Offsets which are far away from the last query fall back to a normal lookup.
+pub struct RopeTextScanner<'a> {
+    text: &'a Rope,
+    cursor: Cursor<'a, RopeInfo>,
+    /// The line of the last query
+    line: usize,
+    /// The offset of the start of `line`
+    line_start: usize,
+    /// The offset of the start of the line after `line`, if there is one
+    next_line_start: Option<usize>,
+}
+
+impl<'a> RopeTextScanner<'a> {
+    /// The maximum number of lines we step over before falling back to a normal lookup
+    const MAX_STEPS: usize = 16;
+
+    pub fn new(text: &'a Rope) -> Self {
+        let mut scanner = Self {
+            text,
+            cursor: Cursor::new(text, 0),
+            line: 0,
+            line_start: 0,
+            next_line_start: None,
+        };
+        scanner.seek(0);
+        scanner
+    }
+
+    /// Get the line of the given offset, equivalent to `RopeText::line_of_offset`
+    pub fn line_of_offset(&mut self, offset: usize) -> usize {
+        let offset = offset.min(self.text.len());
+
+        for _ in 0..Self::MAX_STEPS {
+            if offset < self.line_start {
+                self.prev_line();
+            } else if matches!(self.next_line_start, Some(next) if offset >= next) {
+                self.next_line();
+            } else {
+                return self.line;
+            }
+        }
+
+        self.seek(offset);
+        self.line
+    }
+
+    /// Move to the given line, which must be in bounds, stepping a line at a time if it is
+    /// near the last query. This is what `LineReader` is built on.
+    fn move_to_line(&mut self, line: usize) {
+        if line.abs_diff(self.line) > Self::MAX_STEPS {
+            self.seek_line(line);
+            return;
+        }
+
+        while self.line < line && self.next_line_start.is_some() {
+            self.next_line();
+        }
+        while self.line > line {
+            self.prev_line();
+        }
+    }
+
+    fn seek(&mut self, offset: usize) {
+        let text = RopeText::new(self.text);
+        self.seek_line(text.line_of_offset(offset));
+    }
+
+    fn seek_line(&mut self, line: usize) {
+        self.line = line;
+        self.line_start = self.text.offset_of_line(line);
+        self.cursor.set(self.line_start);
+        self.next_line_start = self.cursor.next::<LinesMetric>();
+    }
+
+    fn next_line(&mut self) {
+        if let Some(next_line_start) = self.next_line_start {
+            self.line += 1;
+            self.line_start = next_line_start;
+            self.cursor.set(next_line_start);
+            self.next_line_start = self.cursor.next::<LinesMetric>();
+        }
+    }
+
+    fn prev_line(&mut self) {
+        if self.line > 0 {
+            self.line -= 1;
+            self.next_line_start = Some(self.line_start);
+            self.cursor.set(self.line_start);
+            self.line_start = self.cursor.prev::<LinesMetric>().unwrap_or(0);
+        }
+    }
+}
+
+/// Looks up the offsets and content of lines, remembering the last line queried so that
+/// reading lines in increasing order (such as when rendering) only has to step forward a
+/// line at a time rather than descending the rope for each line.  
+/// This steps through the lines with a `RopeTextScanner`, so lines which are far from the
+/// last query fall back to a normal lookup.
+/// Created by `RopeText::line_reader`, and gives the same results as the equivalent
+/// `RopeText` methods.
+pub struct LineReader<'a> {
+    scanner: RopeTextScanner<'a>,
+    last_line: usize,
+}
+
+impl<'a> LineReader<'a> {
+    fn new(text: &'a Rope, last_line: usize) -> Self {
+        Self {
+            scanner: RopeTextScanner::new(text),
+            last_line,
+        }
+    }
+
+    /// Get the offset of the start of the line, equivalent to `RopeText::offset_of_line`
+    pub fn start_offset(&mut self, line: usize) -> usize {
+        if line > self.last_line {
+            return self.scanner.text.len();
+        }
+
+        self.scanner.move_to_line(line);
+        self.scanner.line_start
+    }
+
+    /// Get the offset of the end of the line, after its line ending, which is the start of
+    /// the next line
+    pub fn end_offset(&mut self, line: usize) -> usize {
+        if line > self.last_line {
+            return self.scanner.text.len();
+        }
+
+        self.scanner.move_to_line(line);
+        self.scanner
+            .next_line_start
+            .unwrap_or_else(|| self.scanner.text.len())
+    }
+
+    /// Get the content of the line, including its line ending, equivalent to
+    /// `RopeText::line_content`
+    pub fn content(&mut self, line: usize) -> Cow<'a, str> {
+        let start = self.start_offset(line);
+        let end = self.end_offset(line);
+        self.scanner.text.slice_to_cow(start..end)
+    }
+}
+
+/// Joins an iterator of iterators over char indices `(usize, char)` into one
+/// as if they were from a single long string
+/// Assumes the iterators end after the first `None` value
+#[derive(Clone)]
+pub struct CharIndicesJoin<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>>
+{
+    /// Our iterator of iterators
+    main_iter: O,
+    /// Our current working iterator of indices
+    current_indices: Option<I>,
+    /// The amount we should shift future offsets
+    current_base: usize,
+    /// The latest base, since we don't know when the `current_indices` iterator will end
+    latest_base: usize,
+    /// The item we looked ahead at with `peek`, which `next` returns before continuing
+    peeked: Option<(usize, char)>,
+}
+
+impl<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>>
+    CharIndicesJoin<I, O>
+{
+    pub fn new(main_iter: O) -> CharIndicesJoin<I, O> {
+        CharIndicesJoin {
+            main_iter,
+            current_indices: None,
+            current_base: 0,
+            latest_base: 0,
+            peeked: None,
+        }
+    }
+
+    /// Look at the next item without consuming it, like `Peekable::peek`, but without having
+    /// to wrap (or clone) the iterator.
+    pub fn peek(&mut self) -> Option<&(usize, char)> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_joined();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn next_joined(&mut self) -> Option<(usize, char)> {
+        loop {
+            if let Some(current) = &mut self.current_indices {
+                if let Some((next_offset, next_ch)) = current.next() {
+                    // Shift by the current base offset, which is the accumulated offset from
+                    // previous iterators, which makes so the offset produced looks like it is
+                    // from one long str
+                    let next_offset = self.current_base + next_offset;
+                    // Store the latest base offset, because we don't know when the current
+                    // iterator will end (though technically the str iterator impl does)
+                    self.latest_base = next_offset + next_ch.len_utf8();
+                    return Some((next_offset, next_ch));
+                }
+            }
+
+            // Otherwise, if we didn't return something above, then we get a next iterator
+            // If we didn't get anything from the main iter, then we're completely done.
+            let next_current = self.main_iter.next()?;
+            // Update our current working iterator
+            self.current_indices = Some(next_current);
+            // Update the current base offset with the previous iterators latest offset base
+            // This is what we are shifting by
+            self.current_base = self.latest_base;
+
+            // Then loop around to get the next item from the new current iterator
+            // We loop rather than recursing so that many empty iterators in a row don't grow
+            // the stack
+        }
+    }
+}
+
+impl<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>> Iterator
+    for CharIndicesJoin<I, O>
+{
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peeked.take().or_else(|| self.next_joined())
+    }
+}
+
+/// Joins an iterator of reversed iterators over char indices `(usize, char)` into one,
+/// as if they were from a single long string iterated from the end  
+/// The iterators should come from the last chunk to the first, and `len` is the total length
+/// of the chunks, so that the offsets can be shifted before we've seen the earlier chunks.
+/// Assumes the iterators end after the first `None` value
+pub struct RevCharIndicesJoin<
+    I: Iterator<Item = (usize, char)>,
+    O: Iterator<Item = I>,
+> {
+    /// Our iterator of iterators
+    main_iter: O,
+    /// Our current working iterator of indices
+    current_indices: Option<I>,
+    /// The amount we should shift the offsets of the current iterator
+    current_base: usize,
+}
+
+impl<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>>
+    RevCharIndicesJoin<I, O>
+{
+    pub fn new(main_iter: O, len: usize) -> RevCharIndicesJoin<I, O> {
+        RevCharIndicesJoin {
+            main_iter,
+            current_indices: None,
+            current_base: len,
+        }
+    }
+}
+
+impl<I: Iterator<Item = (usize, char)>, O: Iterator<Item = I>> Iterator
+    for RevCharIndicesJoin<I, O>
+{
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(current) = &mut self.current_indices {
+            if let Some((next_offset, next_ch)) = current.next() {
+                return Some((self.current_base + next_offset, next_ch));
+            }
+        }
+
+        // Otherwise, if we didn't return something above, then we get a next iterator,
+        // skipping over any empty ones
+        loop {
+            let mut next_current = self.main_iter.next()?;
+            if let Some((next_offset, next_ch)) = next_current.next() {
+                // The first char we get is the last one of its chunk, so it tells us the
+                // length of the chunk, which is how far back the base has to move
+                let chunk_len = next_offset + next_ch.len_utf8();
+                self.current_base = self.current_base.saturating_sub(chunk_len);
+                self.current_indices = Some(next_current);
+                return Some((self.current_base + next_offset, next_ch));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use lapce_xi_rope::Rope;
+    use lsp_types::Position;
+    use regex::Regex;
+
+    use crate::mode::Mode;
+
+    use super::{
+        CharIndicesJoin, LineChange, LineEnding, RevCharIndicesJoin, RopeText,
+        RopeTextScanner,
+    };
+
+    #[test]
+    fn test_starts_with_bom() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert!(!text.starts_with_bom());
+        assert_eq!(text.content_start_offset(), 0);
+
+        let text = Rope::from("\u{feff}ab\ncd");
+        let text = RopeText::new(&text);
+
+        assert!(text.starts_with_bom());
+        assert_eq!(text.content_start_offset(), 3);
+        assert_eq!(text.slice_to_cow(text.content_start_offset()..5), "ab");
+
+        // Only the BOM
+        let text = Rope::from("\u{feff}");
+        let text = RopeText::new(&text);
+
+        assert!(text.starts_with_bom());
+        assert_eq!(text.content_start_offset(), 3);
+
+        // A BOM that isn't at the start isn't skipped
+        let text = Rope::from("a\u{feff}b");
+        let text = RopeText::new(&text);
+
+        assert!(!text.starts_with_bom());
+        assert_eq!(text.content_start_offset(), 0);
+
+        // Sharing the first bytes of a BOM
+        let text = Rope::from("\u{fefe}");
+        let text = RopeText::new(&text);
+
+        assert!(!text.starts_with_bom());
+
+        // Shorter than a BOM
+        let text = Rope::from("ab");
+        let text = RopeText::new(&text);
+
+        assert!(!text.starts_with_bom());
+        assert_eq!(text.content_start_offset(), 0);
+    }
+
+    #[test]
+    fn test_line_content() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content(0), "");
+        assert_eq!(text.line_content(1), "");
+        assert_eq!(text.line_content(2), "");
+
+        let text = Rope::from("abc\ndef\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content(0), "abc\n");
+        assert_eq!(text.line_content(1), "def\n");
+        assert_eq!(text.line_content(2), "ghi");
+        assert_eq!(text.line_content(3), "");
+        assert_eq!(text.line_content(4), "");
+        assert_eq!(text.line_content(5), "");
+
+        let text = Rope::from("abc\r\ndef\r\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content(0), "abc\r\n");
+        assert_eq!(text.line_content(1), "def\r\n");
+        assert_eq!(text.line_content(2), "ghi");
+        assert_eq!(text.line_content(3), "");
+        assert_eq!(text.line_content(4), "");
+        assert_eq!(text.line_content(5), "");
+    }
+
+    #[test]
+    fn test_line_utf16col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_utf16col_of_offset(0), (0, 0));
+        assert_eq!(text.offset_of_line_utf16col(0, 0), 0);
+        assert_eq!(text.offset_of_line_utf16col(0, 5), 0);
+        assert_eq!(text.offset_of_line_utf16col(5, 5), 0);
+
+        // The emoji is 4 bytes in UTF8 but 2 code units in UTF16
+        let text = Rope::from("a\u{1F600}b\r\n\u{1F600}\u{1F600}");
+        let text = RopeText::new(&text);
+
+        let expected = [
+            (0, (0, 0)),
+            (1, (0, 1)),
+            (5, (0, 3)),
+            (6, (0, 4)),
+            (8, (1, 0)),
+            (12, (1, 2)),
+            (16, (1, 4)),
+        ];
+        for (offset, (line, utf16_col)) in expected {
+            assert_eq!(text.line_utf16col_of_offset(offset), (line, utf16_col));
+            assert_eq!(text.offset_of_line_utf16col(line, utf16_col), offset);
+            let pos = text.offset_to_position(offset);
+            assert_eq!(
+                (pos.line as usize, pos.character as usize),
+                (line, utf16_col)
+            );
+        }
+
+        // Past the end of the line's content
+        assert_eq!(text.offset_of_line_utf16col(0, 10), 6);
+        assert_eq!(text.offset_of_line_utf16col(1, 10), 16);
+        assert_eq!(text.offset_of_line_utf16col(10, 0), 16);
+        // Inside of the CRLF
+        assert_eq!(text.line_utf16col_of_offset(7), (0, 4));
+    }
+
+    #[test]
+    fn test_utf16_offset() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.utf16_offset_of(0), 0);
+        assert_eq!(text.utf16_offset_of(10), 0);
+        assert_eq!(text.utf8_offset_of_utf16(0), 0);
+        assert_eq!(text.utf8_offset_of_utf16(10), 0);
+
+        // The emoji is 4 bytes in UTF8 but 2 code units in UTF16, and the e with an acute is
+        // 2 bytes but 1 code unit
+        let text = Rope::from("a\u{1F600}b\n\u{00e9}\u{1F600}");
+        let text = RopeText::new(&text);
+
+        let expected = [(0, 0), (1, 1), (5, 3), (6, 4), (7, 5), (9, 6), (13, 8)];
+        for (utf8_offset, utf16_offset) in expected {
+            assert_eq!(text.utf16_offset_of(utf8_offset), utf16_offset);
+            assert_eq!(text.utf8_offset_of_utf16(utf16_offset), utf8_offset);
+        }
+        // In the middle of a codepoint, or a surrogate pair
+        assert_eq!(text.utf16_offset_of(3), 1);
+        assert_eq!(text.utf8_offset_of_utf16(2), 5);
+        // Past the end
+        assert_eq!(text.utf16_offset_of(100), 8);
+        assert_eq!(text.utf8_offset_of_utf16(100), 13);
+
+        // Across many chunks, the offsets only ever increase together
+        let content = "abc\u{00e9}\u{1F600}\n".repeat(2000);
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+
+        let mut last_utf16_offset = 0;
+        for (utf8_offset, _) in content.char_indices().step_by(7) {
+            let utf16_offset = text.utf16_offset_of(utf8_offset);
+            assert_eq!(utf16_offset, content[..utf8_offset].encode_utf16().count());
+            assert!(utf16_offset >= last_utf16_offset);
+            assert_eq!(text.utf8_offset_of_utf16(utf16_offset), utf8_offset);
+            last_utf16_offset = utf16_offset;
+        }
+        assert_eq!(
+            text.utf16_offset_of(content.len()),
+            content.encode_utf16().count()
+        );
+    }
+
+    #[test]
+    fn test_write_range() {
+        let write = |text: &RopeText, range: std::ops::Range<usize>| {
+            let mut out = String::new();
+            text.write_range(range, &mut out).unwrap();
+            out
+        };
+
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+        assert_eq!(write(&text, 0..0), "");
+        assert_eq!(write(&text, 0..10), "");
+
+        let text = Rope::from("abc\r\ndef\nghi");
+        let text = RopeText::new(&text);
+        assert_eq!(write(&text, 0..12), "abc\r\ndef\nghi");
+        assert_eq!(write(&text, 4..9), "\ndef\n");
+        assert_eq!(write(&text, 9..100), "ghi");
+
+        // A range spanning many chunks of the rope
+        let content = (0..5000)
+            .map(|i| format!("line {i} \u{00e9}\u{1F600}\n"))
+            .collect::<String>();
+        let text = Rope::from(content);
+        let text = RopeText::new(&text);
+        let start = text.offset_of_line(3);
+        let end = text.offset_of_line(4900);
+        assert_eq!(write(&text, start..end), text.slice_to_cow(start..end));
+        assert_eq!(
+            write(&text, 0..text.len()),
+            text.slice_to_cow(0..text.len())
+        );
+    }
+
+    #[test]
+    fn test_line_content_trimmed() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_trimmed(0), "");
+        assert_eq!(text.line_content_trimmed(1), "");
+
+        let text = Rope::from("abc\ndef\r\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_trimmed(0), "abc");
+        assert_eq!(text.line_content_trimmed(1), "def");
+        assert_eq!(text.line_content_trimmed(2), "ghi");
+        assert_eq!(text.line_content_trimmed(3), "");
+
+        // Trimming the ending doesn't allocate when the line is already borrowed
+        for line in 0..3 {
+            assert!(matches!(text.line_content(line), Cow::Borrowed(_)));
+            assert!(matches!(text.line_content_trimmed(line), Cow::Borrowed(_)));
+        }
+    }
+
+    #[test]
+    fn test_line_len_without_ending() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_len_without_ending(0), 0);
+        assert_eq!(text.line_len_without_ending(1), 0);
+
+        let text = Rope::from("abc\nde\r\n\n\r\nghij");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_len_without_ending(0), 3);
+        assert_eq!(text.line_len(0), 4);
+        assert_eq!(text.line_len_without_ending(1), 2);
+        assert_eq!(text.line_len(1), 4);
+        // Empty lines
+        assert_eq!(text.line_len_without_ending(2), 0);
+        assert_eq!(text.line_len_without_ending(3), 0);
+        // The final line has no line ending
+        assert_eq!(text.line_len_without_ending(4), 4);
+        assert_eq!(text.line_len(4), 4);
+        assert_eq!(text.line_len_without_ending(5), 0);
+
+        let text = Rope::from("abc\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_len_without_ending(0), 3);
+        assert_eq!(text.line_len_without_ending(1), 0);
+    }
+
+    #[test]
+    fn test_line_max() {
+        // Lines far out of bounds, such as from a saturating computation, should not
+        // overflow
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_len(usize::MAX), 0);
+        assert_eq!(text.line_content(usize::MAX), "");
+
+        let text = Rope::from("abc\r\ndef\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_len(usize::MAX), 0);
+        assert_eq!(text.line_len_without_ending(usize::MAX), 0);
+        assert_eq!(text.line_content(usize::MAX), "");
+        assert_eq!(text.line_content_trimmed(usize::MAX), "");
+        assert_eq!(text.line_content_split(usize::MAX), ("".into(), ""));
+        assert_eq!(text.line_ends(usize::MAX), (12, 12));
+        assert_eq!(text.line_end_offset(usize::MAX, true), 12);
+        assert_eq!(text.line_end_col(usize::MAX, true), 0);
+        assert_eq!(text.offset_of_line_col(usize::MAX, 5), 12);
+        assert!(text.is_line_blank(usize::MAX));
+        assert_eq!(text.trailing_whitespace_range(usize::MAX), None);
+        assert_eq!(text.line_ending_at(usize::MAX), None);
+        assert_eq!(text.first_non_blank_character_on_line(usize::MAX), 9);
+        assert_eq!(text.last_non_blank_character_on_line(usize::MAX), 12);
+        assert_eq!(text.line_grapheme_len(usize::MAX), 0);
+        assert_eq!(text.indent_on_line(usize::MAX), "");
+    }
+
+    #[test]
+    fn test_line_content_split() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_split(0), ("".into(), ""));
+        assert_eq!(text.line_content_split(1), ("".into(), ""));
+
+        let text = Rope::from("abc\ndef\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_split(0), ("abc".into(), "\n"));
+        assert_eq!(text.line_content_split(1), ("def".into(), "\n"));
+        assert_eq!(text.line_content_split(2), ("ghi".into(), ""));
+        assert_eq!(text.line_content_split(3), ("".into(), ""));
+
+        let text = Rope::from("abc\r\ndef\r\nghi\r\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_split(0), ("abc".into(), "\r\n"));
+        assert_eq!(text.line_content_split(1), ("def".into(), "\r\n"));
+        assert_eq!(text.line_content_split(2), ("ghi".into(), "\r\n"));
+        assert_eq!(text.line_content_split(3), ("".into(), ""));
+
+        let text = Rope::from("abc\n\r\n\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_split(0), ("abc".into(), "\n"));
+        assert_eq!(text.line_content_split(1), ("".into(), "\r\n"));
+        assert_eq!(text.line_content_split(2), ("".into(), "\n"));
+    }
+
+    #[test]
+    fn test_is_line_blank() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert!(text.is_line_blank(0));
+        assert!(text.is_line_blank(1));
+
+        let text = Rope::from("a  \n\t\nb\r\n  \r\n");
+        let text = RopeText::new(&text);
+
+        assert!(!text.is_line_blank(0));
+        assert!(text.is_line_blank(1));
+        assert!(!text.is_line_blank(2));
+        assert!(text.is_line_blank(3));
+        assert!(text.is_line_blank(4));
+    }
+
+    #[test]
+    fn test_paragraph_at() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.paragraph_at(0), 0..0);
+        assert_eq!(text.paragraph_at(3), 0..0);
+
+        let text = Rope::from("a\nb\nc\n\n  \nd\r\ne\n\nf");
+        let text = RopeText::new(&text);
+
+        // From the middle line, and from the first and last lines of the paragraph
+        assert_eq!(text.paragraph_at(1), 0..3);
+        assert_eq!(text.paragraph_at(0), 0..3);
+        assert_eq!(text.paragraph_at(2), 0..3);
+        // Blank lines, including ones with only whitespace
+        assert_eq!(text.paragraph_at(3), 3..3);
+        assert_eq!(text.paragraph_at(4), 4..4);
+        assert_eq!(text.paragraph_at(5), 5..7);
+        assert_eq!(text.paragraph_at(6), 5..7);
+        assert_eq!(text.paragraph_at(7), 7..7);
+        // Bounded by the end of the buffer
+        assert_eq!(text.paragraph_at(8), 8..9);
+        assert_eq!(text.paragraph_at(100), 8..9);
+
+        let text = Rope::from("a\nb\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.paragraph_at(0), 0..2);
+        assert_eq!(text.paragraph_at(2), 2..2);
+    }
+
+    #[test]
+    fn test_is_blank_between() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert!(text.is_blank_between(0, 0));
+        assert!(text.is_blank_between(0, 10));
+
+        let text = Rope::from("a   \n\t  b");
+        let text = RopeText::new(&text);
+
+        assert!(text.is_blank_between(1, 8));
+        assert!(text.is_blank_between(1, 4));
+        assert!(text.is_blank_between(4, 8));
+        assert!(!text.is_blank_between(0, 8));
+        assert!(!text.is_blank_between(1, 9));
+        assert!(!text.is_blank_between(8, 100));
+        assert!(text.is_blank_between(9, 100));
+        // Inverted ranges are empty
+        assert!(text.is_blank_between(9, 0));
+    }
+
+    #[test]
+    fn test_trailing_whitespace_range() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.trailing_whitespace_range(0), None);
+        assert_eq!(text.trailing_whitespace_range(1), None);
+
+        let text = Rope::from("a  \n\t\nb");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.trailing_whitespace_range(0), Some(1..3));
+        assert_eq!(text.trailing_whitespace_range(1), Some(4..5));
+        assert_eq!(text.trailing_whitespace_range(2), None);
+        assert_eq!(text.trailing_whitespace_range(3), None);
+
+        // The line ending is never included
+        let text = Rope::from("a \t\r\nb \t");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.trailing_whitespace_range(0), Some(1..3));
+        assert_eq!(text.trailing_whitespace_range(1), Some(6..8));
+    }
+
+    #[test]
+    fn test_trailing_whitespace_edits_for_lines() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert!(text.trailing_whitespace_edits_for_lines(&[]).is_empty());
+        assert!(text.trailing_whitespace_edits_for_lines(&[0]).is_empty());
+
+        let text = Rope::from("a  \nb\t\nc\nd \r\ne ");
+        let text = RopeText::new(&text);
+
+        assert!(text.trailing_whitespace_edits_for_lines(&[]).is_empty());
+        assert_eq!(text.trailing_whitespace_edits_for_lines(&[1]), vec![5..6]);
+        assert!(text.trailing_whitespace_edits_for_lines(&[2]).is_empty());
+        assert_eq!(
+            text.trailing_whitespace_edits_for_lines(&[4, 0, 3]),
+            vec![1..3, 10..11, 14..15]
+        );
+        assert_eq!(
+            text.trailing_whitespace_edits_for_lines(&[1, 1, 2]),
+            vec![5..6]
+        );
+    }
+
+    #[test]
+    fn test_line_grapheme_len() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_grapheme_len(0), 0);
+        assert_eq!(text.line_grapheme_len(1), 0);
+
+        let text = Rope::from("e\u{0301}llo\r\ne\u{0301}llo\n\n\u{1F1FA}\u{1F1F8}!");
+        let text = RopeText::new(&text);
+
+        // 6 bytes, but 4 graphemes, and the line ending isn't counted
+        assert_eq!(text.line_grapheme_len(0), 4);
+        assert_eq!(text.line_grapheme_len(1), 4);
+        assert_eq!(text.line_grapheme_len(2), 0);
+        assert_eq!(text.line_grapheme_len(3), 2);
+        assert_eq!(text.line_grapheme_len(4), 0);
+    }
+
+    #[test]
+    fn test_grapheme_count() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.grapheme_count(0..0), 0);
+        assert_eq!(text.grapheme_count(0..10), 0);
+
+        let text = Rope::from("e\u{0301}llo\r\nx");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.grapheme_count(0..text.len()), 6);
+        assert_eq!(text.grapheme_count(0..6), 4);
+        // The CRLF is a single grapheme
+        assert_eq!(text.grapheme_count(6..8), 1);
+        assert_eq!(text.grapheme_count(3..3), 0);
+        // Partially overlapping the combining character
+        assert_eq!(text.grapheme_count(0..1), 1);
+        assert_eq!(text.grapheme_count(0..100), 6);
+    }
+
+    #[test]
+    fn test_grapheme_widths() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.grapheme_widths(0, 4).count(), 0);
+        assert_eq!(text.grapheme_widths(1, 4).count(), 0);
+
+        let text = Rope::from("a\t\u{3042}\u{0301}");
+        let text = RopeText::new(&text);
+
+        // The combining mark is part of the wide character's cluster and adds no width
+        let widths: Vec<_> = text.grapheme_widths(0, 4).collect();
+        assert_eq!(widths, vec![(0, 1), (1, 3), (2, 2)]);
+        let widths: Vec<_> = text.grapheme_widths(0, 8).collect();
+        assert_eq!(widths, vec![(0, 1), (1, 7), (2, 2)]);
+
+        let text = Rope::from("x\r\n\t\u{0301}\u{3042}\t\n");
+        let text = RopeText::new(&text);
+
+        let widths: Vec<_> = text.grapheme_widths(0, 4).collect();
+        assert_eq!(widths, vec![(0, 1)]);
+        // A combining mark after a tab is its own zero width cluster
+        let widths: Vec<_> = text.grapheme_widths(1, 4).collect();
+        assert_eq!(widths, vec![(3, 4), (4, 0), (6, 2), (9, 2)]);
+        assert_eq!(text.grapheme_widths(2, 4).count(), 0);
+    }
+
+    #[test]
+    fn test_visual_col_of_offset() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.visual_col_of_offset(0, 4), 0);
+        assert_eq!(text.visual_col_of_offset(5, 4), 0);
+
+        let text = Rope::from("\tabc");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.visual_col_of_offset(0, 4), 0);
+        assert_eq!(text.visual_col_of_offset(1, 4), 4);
+        assert_eq!(text.visual_col_of_offset(2, 4), 5);
+        assert_eq!(text.visual_col_of_offset(4, 4), 7);
+        assert_eq!(text.visual_col_of_offset(1, 8), 8);
+        // Out of bounds
+        assert_eq!(text.visual_col_of_offset(10, 4), 7);
+
+        // Mixed tabs and spaces
+        let text = Rope::from("ab\n  \tx\t\r\ny");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.visual_col_of_offset(5, 4), 2);
+        assert_eq!(text.visual_col_of_offset(6, 4), 4);
+        assert_eq!(text.visual_col_of_offset(7, 4), 5);
+        assert_eq!(text.visual_col_of_offset(8, 4), 8);
+        // The line ending doesn't take up any columns
+        assert_eq!(text.visual_col_of_offset(9, 4), 8);
+        assert_eq!(text.visual_col_of_offset(10, 4), 0);
+
+        // Grapheme clusters are a single column
+        let text = Rope::from("e\u{0301}x\u{1F600}y");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.visual_col_of_offset(1, 4), 0);
+        assert_eq!(text.visual_col_of_offset(3, 4), 1);
+        assert_eq!(text.visual_col_of_offset(4, 4), 2);
+        assert_eq!(text.visual_col_of_offset(6, 4), 2);
+        // The emoji is two columns wide
+        assert_eq!(text.visual_col_of_offset(8, 4), 4);
+    }
+
+    #[test]
+    fn test_offset_of_visual_col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_visual_col(0, 0, 4), 0);
+        assert_eq!(text.offset_of_visual_col(0, 5, 4), 0);
+
+        let text = Rope::from("\tabc");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_visual_col(0, 0, 4), 0);
+        assert_eq!(text.offset_of_visual_col(0, 2, 4), 0);
+        assert_eq!(text.offset_of_visual_col(0, 4, 4), 1);
+        assert_eq!(text.offset_of_visual_col(0, 5, 4), 2);
+        assert_eq!(text.offset_of_visual_col(0, 7, 4), 4);
+        assert_eq!(text.offset_of_visual_col(0, 100, 4), 4);
+
+        let text = Rope::from("ab\n  \tx\t\r\ny");
+        let text = RopeText::new(&text);
+
+        for offset in 3..9 {
+            let col = text.visual_col_of_offset(offset, 4);
+            assert_eq!(text.offset_of_visual_col(1, col, 4), offset);
+        }
+        assert_eq!(text.offset_of_visual_col(1, 100, 4), 8);
+        assert_eq!(text.offset_of_visual_col(2, 1, 4), 11);
+
+        let text = Rope::from("e\u{0301}x\u{1F600}y");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_visual_col(0, 1, 4), 3);
+        assert_eq!(text.offset_of_visual_col(0, 3, 4), 4);
+        assert_eq!(text.offset_of_visual_col(0, 4, 4), 8);
+    }
+
+    #[test]
+    fn test_char_at() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.char_at(0), None);
+        assert_eq!(text.char_at(1), None);
+
+        let text = Rope::from("ab\r\n\u{1F1FA}\u{1F1F8}");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.char_at(0), Some('a'));
+        assert_eq!(text.char_at(1), Some('b'));
+        assert_eq!(text.char_at(2), Some('\r'));
+        assert_eq!(text.char_at(3), Some('\n'));
+        assert_eq!(text.char_at(4), Some('\u{1F1FA}'));
+        // Inside of a codepoint
+        assert_eq!(text.char_at(6), Some('\u{1F1FA}'));
+        assert_eq!(text.char_at(8), Some('\u{1F1F8}'));
+        assert_eq!(text.char_at(12), None);
+        assert_eq!(text.char_at(20), None);
+    }
+
+    #[test]
+    fn test_chars_around() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.chars_around(0), (None, None));
+        assert_eq!(text.chars_around(3), (None, None));
+
+        let text = Rope::from("(\u{00e9}\u{1F600})\nx");
+        let text = RopeText::new(&text);
+
+        // Start of the document
+        assert_eq!(text.chars_around(0), (None, Some('(')));
+        assert_eq!(text.chars_around(1), (Some('('), Some('\u{00e9}')));
+        assert_eq!(text.chars_around(3), (Some('\u{00e9}'), Some('\u{1F600}')));
+        // Inside of the emoji
+        assert_eq!(text.chars_around(5), (Some('\u{00e9}'), Some('\u{1F600}')));
+        assert_eq!(text.chars_around(7), (Some('\u{1F600}'), Some(')')));
+        assert_eq!(text.chars_around(8), (Some(')'), Some('\n')));
+        assert_eq!(text.chars_around(9), (Some('\n'), Some('x')));
+        // End of the document
+        assert_eq!(text.chars_around(10), (Some('x'), None));
+        assert_eq!(text.chars_around(20), (Some('x'), None));
+    }
+
+    #[test]
+    fn test_grapheme_at() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.grapheme_at(0), None);
+        assert_eq!(text.grapheme_at(1), None);
+
+        let flag = "\u{1F1FA}\u{1F1F8}";
+        let text = Rope::from(format!("ab\r\n{flag}e\u{0301}"));
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.grapheme_at(0).as_deref(), Some("a"));
+        assert_eq!(text.grapheme_at(1).as_deref(), Some("b"));
+        // A CRLF is a single grapheme cluster
+        assert_eq!(text.grapheme_at(2).as_deref(), Some("\r\n"));
+        assert_eq!(text.grapheme_at(3).as_deref(), Some("\r\n"));
+        // Both regional indicators make up the flag
+        for offset in 4..12 {
+            assert_eq!(text.grapheme_at(offset).as_deref(), Some(flag));
+        }
+        assert_eq!(text.grapheme_at(12).as_deref(), Some("e\u{0301}"));
+        assert_eq!(text.grapheme_at(14).as_deref(), Some("e\u{0301}"));
+        assert_eq!(text.grapheme_at(15), None);
+    }
+
+    #[test]
+    fn test_matching_bracket_offset() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.matching_bracket_offset(0), None);
+
+        let text = Rope::from("(a (b) c)");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.matching_bracket_offset(0), Some(8));
+        assert_eq!(text.matching_bracket_offset(8), Some(0));
+        assert_eq!(text.matching_bracket_offset(3), Some(5));
+        assert_eq!(text.matching_bracket_offset(5), Some(3));
+        // Not a bracket
+        assert_eq!(text.matching_bracket_offset(1), None);
+        assert_eq!(text.matching_bracket_offset(9), None);
+
+        let text = Rope::from("[{\u{1F600}\n}] ([)");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.matching_bracket_offset(0), Some(8));
+        assert_eq!(text.matching_bracket_offset(1), Some(7));
+        assert_eq!(text.matching_bracket_offset(7), Some(1));
+        // Unmatched
+        assert_eq!(text.matching_bracket_offset(11), None);
+        assert_eq!(text.matching_bracket_offset(10), Some(12));
+
+        let text = Rope::from("(()");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.matching_bracket_offset(0), None);
+        assert_eq!(text.matching_bracket_offset(1), Some(2));
+
+        let text = Rope::from("())");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.matching_bracket_offset(2), None);
+        assert_eq!(text.matching_bracket_offset(1), Some(0));
+    }
+
+    #[test]
+    fn test_char_indices_rev_iter() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.char_indices_rev_iter(..).next(), None);
+
+        let text = Rope::from("a\u{00e9}\u{1F600}\nb");
+        let text = RopeText::new(&text);
+
+        let chars: Vec<_> = text.char_indices_rev_iter(..).collect();
+        assert_eq!(
+            chars,
+            vec![
+                (8, 'b'),
+                (7, '\n'),
+                (3, '\u{1F600}'),
+                (1, '\u{00e9}'),
+                (0, 'a')
+            ]
+        );
+        // Offsets are relative to the start of the range
+        let chars: Vec<_> = text.char_indices_rev_iter(1..7).collect();
+        assert_eq!(chars, vec![(2, '\u{1F600}'), (0, '\u{00e9}')]);
+
+        // Enough text that the rope is split into multiple chunks
+        let text = Rope::from("ab\u{00e9}c\u{1F600}\r\n".repeat(500));
+        let text = RopeText::new(&text);
+
+        let ranges = [0..text.len(), 4..text.len(), 0..2002, 1023..4097, 55..55];
+        for range in ranges {
+            let mut forward: Vec<_> =
+                text.char_indices_iter(range.clone()).collect();
+            forward.reverse();
+            let backward: Vec<_> =
+                text.char_indices_rev_iter(range.clone()).collect();
+            assert_eq!(forward, backward, "{range:?}");
+        }
+
+        let mut forward: Vec<_> = text.char_indices_iter(100..).collect();
+        forward.reverse();
+        let backward: Vec<_> = text.char_indices_rev_iter(100..).collect();
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn test_find_with_context() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.find_with_context(0, "a", 3), None);
+
+        let text = Rope::from("let abc = 1;\nabc\r\nx e\u{0301}abcd\u{1F600}!");
+        let text = RopeText::new(&text);
+
+        let (offset, snippet, highlight) =
+            text.find_with_context(0, "abc", 3).unwrap();
+        assert_eq!(offset, 4);
+        assert_eq!(snippet, "et abc = ");
+        assert_eq!(&snippet[highlight], "abc");
+
+        // The context stops at the edges of the line
+        let (offset, snippet, highlight) =
+            text.find_with_context(5, "abc", 3).unwrap();
+        assert_eq!(offset, 13);
+        assert_eq!(snippet, "abc");
+        assert_eq!(highlight, 0..3);
+
+        // The context is in grapheme clusters
+        let (offset, snippet, highlight) =
+            text.find_with_context(14, "abc", 2).unwrap();
+        assert_eq!(offset, 23);
+        assert_eq!(snippet, " e\u{0301}abcd\u{1F600}");
+        assert_eq!(&snippet[highlight], "abc");
+
+        let (_, snippet, highlight) =
+            text.find_with_context(0, "1;\nab", 2).unwrap();
+        assert_eq!(snippet, "= 1;\nabc");
+        assert_eq!(&snippet[highlight], "1;\nab");
+
+        let (_, snippet, highlight) = text.find_with_context(0, "let", 0).unwrap();
+        assert_eq!(snippet, "let");
+        assert_eq!(highlight, 0..3);
+
+        assert_eq!(text.find_with_context(24, "abc", 3), None);
+    }
+
+    #[test]
+    fn test_lines_in_range() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        let lines: Vec<_> = text.lines_in_range(0..0).collect();
+        assert_eq!(lines, vec![(0, "".into())]);
+        let lines: Vec<_> = text.lines_in_range(0..10).collect();
+        assert_eq!(lines, vec![(0, "".into())]);
+
+        let text = Rope::from("a\nb\nc\nd");
+        let text = RopeText::new(&text);
+
+        let lines: Vec<_> = text.lines_in_range(3..5).collect();
+        assert_eq!(lines, vec![(1, "b\n".into()), (2, "c\n".into())]);
+        let lines: Vec<_> = text.lines_in_range(2..7).collect();
+        assert_eq!(
+            lines,
+            vec![(1, "b\n".into()), (2, "c\n".into()), (3, "d".into())]
+        );
+        // A range ending at the start of a line does not include that line
+        let lines: Vec<_> = text.lines_in_range(0..4).collect();
+        assert_eq!(lines, vec![(0, "a\n".into()), (1, "b\n".into())]);
+        let lines: Vec<_> = text.lines_in_range(4..4).collect();
+        assert_eq!(lines, vec![(2, "c\n".into())]);
+
+        let expected: Vec<_> =
+            (0..4).map(|line| (line, text.line_content(line))).collect();
+        let lines: Vec<_> = text.lines_in_range(0..text.len()).collect();
+        assert_eq!(lines, expected);
+
+        let text = Rope::from("a\r\nb\n");
+        let text = RopeText::new(&text);
+
+        let lines: Vec<_> = text.lines_in_range(0..5).collect();
+        assert_eq!(lines, vec![(0, "a\r\n".into()), (1, "b\n".into())]);
+        let lines: Vec<_> = text.lines_in_range(5..5).collect();
+        assert_eq!(lines, vec![(2, "".into())]);
+    }
+
+    #[test]
+    fn test_count_lines_matching() {
+        let is_blank = |line: &str| line.trim().is_empty();
+        let is_fn = |line: &str| line.starts_with("fn ");
+
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.count_lines_matching(is_blank), 1);
+        assert_eq!(text.count_lines_matching(is_fn), 0);
+
+        let text = Rope::from("fn a() {}\n\nfn b() {}\r\n  \r\nlet fn_c = 1;\n");
+        let text = RopeText::new(&text);
+
+        // The empty last line is blank
+        assert_eq!(text.count_lines_matching(is_blank), 3);
+        assert_eq!(text.count_lines_matching(is_fn), 2);
+        // The line endings are trimmed
+        assert_eq!(text.count_lines_matching(|line| line.ends_with('}')), 2);
+
+        let text = RopeText::new(text.text).without_trailing_empty_line();
+        assert_eq!(text.count_lines_matching(is_blank), 2);
+    }
+
+    #[test]
+    fn test_first_line_matching() {
+        let is_blank = |line: &str| line.trim().is_empty();
+        let is_fn = |line: &str| line.starts_with("fn ");
+
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.first_line_matching(0, is_blank), Some(0));
+        assert_eq!(text.first_line_matching(1, is_blank), None);
+        assert_eq!(text.first_line_matching(0, is_fn), None);
+
+        let text = Rope::from("fn a() {}\n\nfn b() {}\r\n  \r\nlet fn_c = 1;\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.first_line_matching(0, is_fn), Some(0));
+        assert_eq!(text.first_line_matching(1, is_fn), Some(2));
+        assert_eq!(text.first_line_matching(3, is_fn), None);
+        assert_eq!(text.first_line_matching(0, is_blank), Some(1));
+        assert_eq!(text.first_line_matching(2, is_blank), Some(3));
+        assert_eq!(text.first_line_matching(4, is_blank), Some(5));
+        assert_eq!(text.first_line_matching(6, is_blank), None);
+        assert_eq!(text.first_line_matching(100, is_blank), None);
+    }
+
+    #[test]
+    fn test_bytes_in_range() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.bytes_in_range(0..0).count(), 0);
+        assert_eq!(text.bytes_in_range(0..10).count(), 0);
+
+        let content = "ab\u{00e9}c\u{1F600}\r\n".repeat(200);
+        let text = Rope::from(&content);
+        let text = RopeText::new(&text);
+
+        for range in [0..0, 0..11, 2..9, 4..text.len(), 1023..2050, 5..10_000] {
+            let bytes: Vec<u8> = text.bytes_in_range(range.clone()).collect();
+            assert_eq!(
+                bytes,
+                text.slice_to_cow(range.clone()).as_bytes(),
+                "{range:?}"
+            );
+        }
+
+        // Ranges in the middle of codepoints
+        for range in [3..7, 6..13, 1000..1100] {
+            let bytes: Vec<u8> = text.bytes_in_range(range.clone()).collect();
+            assert_eq!(bytes, &content.as_bytes()[range.clone()], "{range:?}");
+        }
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let range = 10..5;
+        assert_eq!(text.bytes_in_range(range).count(), 0);
+    }
+
+    #[test]
+    fn test_byte_at() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.byte_at(0), None);
+
+        let text = Rope::from("a\u{00e9}");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.byte_at(0), Some(b'a'));
+        assert_eq!(text.byte_at(1), Some(0xc3));
+        assert_eq!(text.byte_at(2), Some(0xa9));
+        assert_eq!(text.byte_at(3), None);
+    }
+
+    #[test]
+    fn test_find_in_line() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.find_in_line(0, "a", 0), None);
+        assert_eq!(text.find_in_line(5, "a", 0), None);
+
+        let text = Rope::from("abc\nHello hello\n\u{00e9}hello");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.find_in_line(1, "hello", 0), Some(10));
+        assert_eq!(text.find_in_line(1, "Hello", 0), Some(4));
+        assert_eq!(text.find_in_line(1, "hello", 6), Some(10));
+        assert_eq!(text.find_in_line(1, "hello", 7), None);
+        assert_eq!(text.find_in_line(1, "hello", 100), None);
+        // Only the line is searched
+        assert_eq!(text.find_in_line(0, "hello", 0), None);
+        assert_eq!(text.find_in_line(1, "abc", 0), None);
+        // In the middle of the multi-byte character
+        assert_eq!(text.find_in_line(2, "hello", 1), Some(18));
+        assert_eq!(text.find_in_line(2, "\u{00e9}", 1), None);
+    }
+
+    #[test]
+    fn test_find_in_line_ignore_ascii_case() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.find_in_line_ignore_ascii_case(0, "a", 0), None);
+
+        let text = Rope::from("abc\nHello hello\n\u{00e9}hELLO");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.find_in_line_ignore_ascii_case(1, "hello", 0), Some(4));
+        assert_eq!(text.find_in_line_ignore_ascii_case(1, "HELLO", 1), Some(10));
+        assert_eq!(text.find_in_line_ignore_ascii_case(1, "hello", 11), None);
+        assert_eq!(text.find_in_line_ignore_ascii_case(0, "hello", 0), None);
+        assert_eq!(text.find_in_line_ignore_ascii_case(2, "hello", 1), Some(18));
+        // Non-ASCII characters still have to match exactly
+        assert_eq!(text.find_in_line_ignore_ascii_case(2, "\u{00c9}", 0), None);
+        assert_eq!(
+            text.find_in_line_ignore_ascii_case(2, "\u{00e9}H", 0),
+            Some(16)
+        );
+    }
+
+    #[test]
+    fn test_char_count() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.char_count(), 0);
+        assert_eq!(text.char_count_in_range(0..10), 0);
+
+        // e + combining acute is two characters, but one grapheme cluster
+        let text = Rope::from("ab\r\ne\u{0301}\u{1F600}c");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.char_count(), 8);
+        assert_eq!(text.char_count_in_range(0..4), 4);
+        assert_eq!(text.char_count_in_range(4..7), 2);
+        assert_eq!(text.char_count_in_range(7..100), 2);
+        assert_eq!(text.grapheme_count(0..text.len()), 6);
+
+        let content = "abc\u{00e9}\u{1F600}\n".repeat(2000);
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+        assert_eq!(text.char_count(), content.chars().count());
+        assert_eq!(
+            text.char_count_in_range(9..9000),
+            content[9..9000].chars().count()
+        );
+    }
+
+    #[test]
+    fn test_range_hash() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        // The offset basis, for no bytes
+        assert_eq!(text.range_hash(0..0), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(text.range_hash(0..10), text.range_hash(0..0));
+
+        // A known FNV-1a value
+        let text = Rope::from("a");
+        let text = RopeText::new(&text);
+        assert_eq!(text.range_hash(0..1), 0xaf63_dc4c_8601_ec8c);
+
+        let content = "abc\u{00e9}\u{1F600}\n".repeat(2000);
+        let a = Rope::from(content.as_str());
+        let a = RopeText::new(&a);
+        // The same content with different chunking, and different content around the range
+        let mut b = Rope::from("xyz");
+        b.edit(3..3, Rope::from(content.as_str()));
+        let b = RopeText::new(&b);
+
+        assert_eq!(a.range_hash(0..a.len()), b.range_hash(3..b.len()));
+        assert_eq!(a.range_hash(10..15000), b.range_hash(13..15003));
+        assert_ne!(a.range_hash(0..a.len()), b.range_hash(0..b.len()));
+
+        // A single byte change
+        let mut c = Rope::from(content.as_str());
+        c.edit(12000..12001, Rope::from("d"));
+        let c = RopeText::new(&c);
+        assert_ne!(a.range_hash(10..15000), c.range_hash(10..15000));
+        assert_eq!(a.range_hash(10..11000), c.range_hash(10..11000));
+    }
+
+    #[test]
+    fn test_eq_range() {
+        let empty = Rope::from("");
+        let empty = RopeText::new(&empty);
+
+        assert!(empty.eq_range(&empty, 0..0, 0..0));
+        assert!(empty.eq_range(&empty, 0..10, 5..10));
+
+        let a = Rope::from("abc\nhello world\nxyz");
+        let a = RopeText::new(&a);
+        let b = Rope::from("hello world\r\nabc\n");
+        let b = RopeText::new(&b);
+
+        assert!(a.eq_range(&b, 4..15, 0..11));
+        assert!(a.eq_range(&b, 0..4, 13..17));
+        assert!(a.eq_range(&b, 4..9, 0..5));
+        assert!(!a.eq_range(&b, 4..16, 0..12));
+        assert!(!a.eq_range(&b, 0..3, 1..4));
+        // Different lengths
+        assert!(!a.eq_range(&b, 4..15, 0..10));
+        // Empty ranges are equal, wherever they are
+        assert!(a.eq_range(&b, 3..3, 8..8));
+        assert!(a.eq_range(&empty, 2..2, 0..0));
+        // Clamped to the end
+        assert!(a.eq_range(&b, 19..100, 0..0));
+        assert!(a.eq_range(&b, 20..100, 17..100));
+        assert!(!a.eq_range(&b, 16..100, 13..16));
+
+        // Ropes built from many pieces, so that the chunks don't line up
+        let mut c = Rope::from("");
+        let mut d = Rope::from("");
+        let line = "The quick brown fox jumps over the lazy dog.\n";
+        for i in 0..400 {
+            c.edit(c.len()..c.len(), line);
+            if i % 3 == 0 {
+                d.edit(0..0, line);
+            } else {
+                d.edit(d.len()..d.len(), line);
+            }
+        }
+        let mut e = d.clone();
+        e.edit(10000..10001, "X");
+        let c = RopeText::new(&c);
+        let d = RopeText::new(&d);
+        let e = RopeText::new(&e);
+
+        assert!(c.eq_range(&d, 0..c.len(), 0..d.len()));
+        assert!(c.eq_range(&d, 5..c.len(), 5..d.len()));
+        assert!(!c.eq_range(&e, 0..c.len(), 0..e.len()));
+        assert!(c.eq_range(&e, 0..10000, 0..10000));
+        assert!(c.eq_range(&e, 10001..c.len(), 10001..e.len()));
+    }
+
+    #[test]
+    fn test_range_is_ascii() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert!(text.range_is_ascii(0..0));
+        assert!(text.range_is_ascii(0..10));
+
+        let text = Rope::from("abc\ncaf\u{00e9}\nxyz");
+        let text = RopeText::new(&text);
+
+        assert!(text.range_is_ascii(0..7));
+        assert!(!text.range_is_ascii(0..9));
+        assert!(!text.range_is_ascii(7..9));
+        assert!(text.range_is_ascii(9..100));
+
+        // Across many chunks, with the non-ASCII character at the very end
+        let content = "abcdefghij\n".repeat(2000) + "\u{00e9}";
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+        assert!(text.range_is_ascii(0..content.len() - 2));
+        assert!(!text.range_is_ascii(0..content.len()));
+    }
+
+    #[test]
+    fn test_range_contains_char() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert!(!text.range_contains_char(0..10, 'a'));
+
+        let text = Rope::from("abc\ncaf\u{00e9}\nxyz");
+        let text = RopeText::new(&text);
+
+        assert!(text.range_contains_char(0..12, '\u{00e9}'));
+        assert!(!text.range_contains_char(0..7, '\u{00e9}'));
+        assert!(text.range_contains_char(0..4, '\n'));
+        assert!(!text.range_contains_char(0..3, '\n'));
+        assert!(text.range_contains_char(9..100, 'z'));
+        assert!(!text.range_contains_char(9..100, 'a'));
+
+        let content = "abcdefghij\n".repeat(2000) + "\u{1F600}";
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+        assert!(text.range_contains_char(0..content.len(), '\u{1F600}'));
+        assert!(!text.range_contains_char(0..content.len() - 4, '\u{1F600}'));
+    }
+
+    #[test]
+    fn test_char_indices_iter() {
+        let joined = |text: &RopeText, range: std::ops::Range<usize>| {
+            CharIndicesJoin::new(text.text.iter_chunks(range).map(str::char_indices))
+                .collect::<Vec<_>>()
+        };
+
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.char_indices_iter(..).next(), None);
+        assert_eq!(text.char_indices_iter(0..0).next(), None);
+
+        // Small enough to be a single chunk
+        let text = Rope::from("a\u{00e9}\u{1F600}\r\nb");
+        let text = RopeText::new(&text);
+
+        assert!(text.single_chunk(0, text.len()).is_some());
+        for (start, end) in [(0, text.len()), (1, 7), (3, 3), (7, text.len())] {
+            let chars: Vec<_> = text.char_indices_iter(start..end).collect();
+            assert_eq!(chars, joined(&text, start..end), "{start}..{end}");
+        }
+        let chars: Vec<_> = text.char_indices_iter(1..).collect();
+        assert_eq!(
+            chars,
+            vec![
+                (0, '\u{00e9}'),
+                (2, '\u{1F600}'),
+                (6, '\r'),
+                (7, '\n'),
+                (8, 'b')
+            ]
+        );
+
+        // Ranges both within a single chunk and across several
+        let text = Rope::from("ab\u{00e9}c\u{1F600}\r\n".repeat(500));
+        let text = RopeText::new(&text);
+
+        assert!(text.single_chunk(0, text.len()).is_none());
+        let mut single = 0;
+        for start in (0..text.len()).step_by(11 * 37) {
+            for len in [0, 1, 11, 110, 1100, 3300] {
+                let end = (start + len).min(text.len());
+                if text.single_chunk(start, end).is_some() {
+                    single += 1;
+                }
+
+                let chars: Vec<_> = text.char_indices_iter(start..end).collect();
+                assert_eq!(chars, joined(&text, start..end), "{start}..{end}");
+            }
+        }
+        assert!(single > 0);
+    }
+
+    #[test]
+    fn test_graphemes_iter() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.graphemes_iter(0..0).next(), None);
+        assert_eq!(text.graphemes_iter(0..10).next(), None);
+
+        // e + combining acute, CRLF, family emoji joined by ZWJs
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let content = format!("e\u{0301}\r\n{family}x");
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+
+        let graphemes: Vec<_> = text.graphemes_iter(0..text.len()).collect();
+        assert_eq!(
+            graphemes,
+            vec![
+                (0, "e\u{0301}".into()),
+                (3, "\r\n".into()),
+                (5, family.into()),
+                (23, "x".into()),
+            ]
+        );
+
+        let graphemes: Vec<_> = text.graphemes_iter(3..100).collect();
+        assert_eq!(
+            graphemes,
+            vec![(3, "\r\n".into()), (5, family.into()), (23, "x".into())]
+        );
+        // The emoji only partially overlaps the end of the range
+        let graphemes: Vec<_> = text.graphemes_iter(5..10).collect();
+        assert_eq!(graphemes, vec![(5, family.into())]);
+        assert_eq!(text.graphemes_iter(24..30).next(), None);
+    }
+
+    #[test]
+    fn test_chars_take() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.chars_take(0, 5).next(), None);
+        assert_eq!(text.chars_take(5, 5).next(), None);
+
+        let text = Rope::from("\u{feff}\u{00e9}#!x\ny");
+        let text = RopeText::new(&text);
+
+        let chars: Vec<_> = text.chars_take(0, 3).collect();
+        assert_eq!(chars, vec![(0, '\u{feff}'), (3, '\u{00e9}'), (5, '#')]);
+        assert_eq!(text.chars_take(0, 0).count(), 0);
+        assert_eq!(text.chars_take(0, 100).count(), 7);
+        // The offsets are relative to the start
+        let chars: Vec<_> = text.chars_take(3, 2).collect();
+        assert_eq!(chars, vec![(0, '\u{00e9}'), (2, '#')]);
+        assert_eq!(text.chars_take(9, 5).collect::<Vec<_>>(), vec![(0, 'y')]);
+
+        // Stops early in a text with many chunks
+        let content = "\u{1F600}ab\n".repeat(5000);
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+        let chars: Vec<_> = text.chars_take(6, 4).collect();
+        assert_eq!(chars, vec![(0, '\n'), (1, '\u{1F600}'), (5, 'a'), (6, 'b')]);
+    }
+
+    #[test]
+    fn test_char_indices_join() {
+        let chunks = ["", "ab", "", "", "\u{00e9}c", "", "\u{1F600}", ""];
+        let joined: Vec<_> =
+            CharIndicesJoin::new(chunks.iter().map(|chunk| chunk.char_indices()))
+                .collect();
+        let expected: Vec<_> = chunks.concat().char_indices().collect();
+        assert_eq!(joined, expected);
+
+        // Many empty iterators in a row shouldn't grow the stack
+        let empty = || (0..1_000_000).map(|_| "");
+        let chunks = empty().chain(["a\u{00e9}"]).chain(empty()).chain(["b"]);
+        let joined: Vec<_> =
+            CharIndicesJoin::new(chunks.map(str::char_indices)).collect();
+        assert_eq!(joined, vec![(0, 'a'), (1, '\u{00e9}'), (3, 'b')]);
+
+        let chunks = ["", "", ""];
+        let mut joined =
+            CharIndicesJoin::new(chunks.iter().map(|chunk| chunk.char_indices()));
+        assert_eq!(joined.next(), None);
+    }
+
+    #[test]
+    fn test_char_indices_join_peek() {
+        let chunks = ["", "ab", "", "\u{00e9}c", ""];
+        let mut joined =
+            CharIndicesJoin::new(chunks.iter().map(|chunk| chunk.char_indices()));
+        assert_eq!(joined.peek(), Some(&(0, 'a')));
+        assert_eq!(joined.peek(), Some(&(0, 'a')));
+        assert_eq!(joined.next(), Some((0, 'a')));
+        assert_eq!(joined.next(), Some((1, 'b')));
+        assert_eq!(joined.peek(), Some(&(2, '\u{00e9}')));
+        assert_eq!(joined.next(), Some((2, '\u{00e9}')));
+        assert_eq!(joined.next(), Some((4, 'c')));
+        assert_eq!(joined.peek(), None);
+        assert_eq!(joined.next(), None);
+
+        // Peeking across the chunks of a rope
+        let content = "abc\u{00e9}\u{1F600}\n".repeat(2000);
+        let rope = Rope::from(content.as_str());
+        let first_chunk_len = rope.iter_chunks(..).next().unwrap().len();
+        assert!(first_chunk_len < content.len());
+
+        let mut joined =
+            CharIndicesJoin::new(rope.iter_chunks(..).map(str::char_indices));
+        let mut expected = content.char_indices();
+        let mut last_offset = None;
+        while let Some(&(offset, c)) = joined.peek() {
+            if let Some(last_offset) = last_offset {
+                assert!(last_offset < offset);
+            }
+            assert_eq!(joined.next(), Some((offset, c)));
+            assert_eq!(expected.next(), Some((offset, c)));
+            last_offset = Some(offset);
+        }
+        assert_eq!(joined.next(), None);
+        assert_eq!(expected.next(), None);
+        assert!(last_offset.unwrap() >= first_chunk_len);
+    }
+
+    #[test]
+    fn test_rev_char_indices_join() {
+        let chunks = ["", "ab", "", "", "\u{00e9}c", "", "\u{1F600}", ""];
+        let len = chunks.concat().len();
+        let joined: Vec<_> = RevCharIndicesJoin::new(
+            chunks.iter().rev().map(|chunk| chunk.char_indices().rev()),
+            len,
+        )
+        .collect();
+        let expected: Vec<_> = chunks.concat().char_indices().rev().collect();
+        assert_eq!(joined, expected);
+
+        let empty = || (0..1_000_000).map(|_| "");
+        let chunks = empty().chain(["a\u{00e9}"]).chain(empty());
+        let joined: Vec<_> = RevCharIndicesJoin::new(
+            chunks.map(|chunk| chunk.char_indices().rev()),
+            3,
+        )
+        .collect();
+        assert_eq!(joined, vec![(1, '\u{00e9}'), (0, 'a')]);
+    }
+
+    #[test]
+    fn test_matches_iter() {
+        let re = Regex::new(r"\w+").unwrap();
+
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.matches_iter(&re, 0..0).count(), 0);
+        assert_eq!(text.matches_iter(&re, 0..10).count(), 0);
+
+        let text = Rope::from("foo \u{03b1}\u{03b2} bar");
+        let text = RopeText::new(&text);
+
+        let matches: Vec<_> = text.matches_iter(&re, 0..text.len()).collect();
+        assert_eq!(matches, vec![0..3, 4..8, 9..12]);
+        let words: Vec<_> = matches
+            .into_iter()
+            .map(|range| text.slice_to_cow(range))
+            .collect();
+        assert_eq!(words, vec!["foo", "\u{03b1}\u{03b2}", "bar"]);
+
+        // The offsets are into the rope, even when the range doesn't start at zero
+        let matches: Vec<_> = text.matches_iter(&re, 1..10).collect();
+        assert_eq!(matches, vec![1..3, 4..8, 9..10]);
+        assert_eq!(text.slice_to_cow(4..8), "\u{03b1}\u{03b2}");
+        let matches: Vec<_> = text.matches_iter(&re, 6..100).collect();
+        assert_eq!(matches, vec![6..8, 9..12]);
+
+        // Across chunks of the rope
+        let text = Rope::from("\u{03b1}b ".repeat(1000));
+        let text = RopeText::new(&text);
+
+        let matches: Vec<_> = text.matches_iter(&re, 0..text.len()).collect();
+        assert_eq!(matches.len(), 1000);
+        for (i, range) in matches.into_iter().enumerate() {
+            assert_eq!(range, i * 4..i * 4 + 3);
+            assert_eq!(text.slice_to_cow(range), "\u{03b1}b");
+        }
+
+        // Empty matches, which are the same as over a string
+        let content = "ba\u{03b2}aa b";
+        let text = Rope::from(content);
+        let text = RopeText::new(&text);
+
+        for re in [r"a*", r"\b", r""] {
+            let re = Regex::new(re).unwrap();
+            let matches: Vec<_> = text.matches_iter(&re, 0..text.len()).collect();
+            let expected: Vec<_> =
+                re.find_iter(content).map(|m| m.start()..m.end()).collect();
+            assert_eq!(matches, expected);
+        }
+
+        // The iterator doesn't borrow the regex, and only searches as it is advanced
+        let mut matches = {
+            let re = Regex::new(r"a+").unwrap();
+            text.matches_iter(&re, 0..text.len())
+        };
+        assert_eq!(matches.next(), Some(1..2));
+        assert_eq!(matches.next(), Some(4..6));
+        assert_eq!(matches.next(), None);
+        assert_eq!(matches.next(), None);
+    }
+
+    #[test]
+    fn test_move_n_words_forward() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.move_n_words_forward(0, 1), 0);
+
+        let text = Rope::from("foo_bar baz-qux 123");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.move_n_words_forward(0, 0), 0);
+        assert_eq!(text.move_n_words_forward(0, 1), 8);
+        assert_eq!(text.move_n_words_forward(0, 2), 11);
+        assert_eq!(text.move_n_words_forward(0, 3), 12);
+        assert_eq!(text.move_n_words_forward(4, 1), 8);
+        assert_eq!(text.move_n_words_forward(12, 1), 16);
+        // Clamped at the end of the text
+        assert_eq!(text.move_n_words_forward(12, 2), 19);
+        assert_eq!(text.move_n_words_forward(0, 100), 19);
+        assert_eq!(text.move_n_words_forward(19, 1), 19);
+    }
+
+    #[test]
+    fn test_move_n_words_backward() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.move_n_words_backward(0, 1, Mode::Normal), 0);
+
+        let text = Rope::from("foo_bar baz-qux 123");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.move_n_words_backward(19, 0, Mode::Normal), 19);
+        assert_eq!(text.move_n_words_backward(19, 1, Mode::Normal), 16);
+        assert_eq!(text.move_n_words_backward(19, 2, Mode::Normal), 12);
+        assert_eq!(text.move_n_words_backward(19, 3, Mode::Normal), 11);
+        assert_eq!(text.move_n_words_backward(10, 1, Mode::Normal), 8);
+        assert_eq!(text.move_n_words_backward(8, 1, Mode::Normal), 0);
+        // Clamped at the start of the text
+        assert_eq!(text.move_n_words_backward(19, 100, Mode::Normal), 0);
+        assert_eq!(text.move_n_words_backward(0, 1, Mode::Normal), 0);
+
+        assert_eq!(text.move_n_words_backward(19, 3, Mode::Insert), 11);
+    }
+
+    #[test]
+    fn test_grapheme_at_visual_col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.grapheme_at_visual_col(0, 0, 4), None);
+        assert_eq!(text.grapheme_at_visual_col(1, 0, 4), None);
+
+        let text = Rope::from("\tabc\n\t\tx\r\n \ty");
+        let text = RopeText::new(&text);
+
+        // Every column of the tab maps to the tab, starting at column 0
+        for col in 0..4 {
+            assert_eq!(text.grapheme_at_visual_col(0, col, 4), Some((0, 0)));
+        }
+        assert_eq!(text.grapheme_at_visual_col(0, 4, 4), Some((1, 4)));
+        assert_eq!(text.grapheme_at_visual_col(0, 6, 4), Some((3, 6)));
+        assert_eq!(text.grapheme_at_visual_col(0, 7, 4), None);
+
+        // Two leading tabs
+        assert_eq!(text.grapheme_at_visual_col(1, 3, 4), Some((5, 0)));
+        assert_eq!(text.grapheme_at_visual_col(1, 4, 4), Some((6, 4)));
+        assert_eq!(text.grapheme_at_visual_col(1, 7, 4), Some((6, 4)));
+        assert_eq!(text.grapheme_at_visual_col(1, 8, 4), Some((7, 8)));
+        // The line ending is not a grapheme on the line
+        assert_eq!(text.grapheme_at_visual_col(1, 9, 4), None);
+
+        // A tab after a space only extends to the next tab stop
+        assert_eq!(text.grapheme_at_visual_col(2, 0, 4), Some((10, 0)));
+        assert_eq!(text.grapheme_at_visual_col(2, 1, 4), Some((11, 1)));
+        assert_eq!(text.grapheme_at_visual_col(2, 3, 4), Some((11, 1)));
+        assert_eq!(text.grapheme_at_visual_col(2, 4, 4), Some((12, 4)));
+    }
+
+    #[test]
+    fn test_collect_lines() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.collect_lines(0..0), Vec::<String>::new());
+        assert_eq!(text.collect_lines(0..5), vec![""]);
+        assert_eq!(text.collect_lines(1..5), Vec::<String>::new());
+
+        let text = Rope::from("abc\r\ndef\n\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.collect_lines(1..3), vec!["def", ""]);
+        assert_eq!(text.collect_lines(0..4), vec!["abc", "def", "", "ghi"]);
+        // The last line has no line ending, so there is no empty line after it
+        assert_eq!(text.collect_lines(2..10), vec!["", "ghi"]);
+        assert_eq!(text.collect_lines(3..3), Vec::<String>::new());
+
+        let text = Rope::from("abc\ndef\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.collect_lines(0..10), vec!["abc", "def", ""]);
+        let text = text.without_trailing_empty_line();
+        assert_eq!(text.collect_lines(0..10), vec!["abc", "def"]);
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let diff = |old: &str, new: &str| {
+            let old = Rope::from(old);
+            let new = Rope::from(new);
+            RopeText::new(&old).line_diff(&RopeText::new(&new))
+        };
+
+        assert_eq!(diff("", ""), vec![]);
+        assert_eq!(diff("a\nb\nc", "a\nb\nc"), vec![]);
+        // Line endings are ignored
+        assert_eq!(diff("a\nb\nc", "a\r\nb\r\nc"), vec![]);
+
+        // Insertion
+        assert_eq!(
+            diff("a\nb\nc", "a\nx\ny\nb\nc"),
+            vec![LineChange::Inserted {
+                old_line: 1,
+                new: 1..3
+            }]
+        );
+        assert_eq!(
+            diff("", "a"),
+            vec![LineChange::Changed {
+                old: 0..1,
+                new: 0..1
+            }]
+        );
+        assert_eq!(
+            diff("a", "a\nb"),
+            vec![LineChange::Inserted {
+                old_line: 1,
+                new: 1..2
+            }]
+        );
+
+        // Deletion
+        assert_eq!(
+            diff("a\nb\nc\nd", "a\nd"),
+            vec![LineChange::Removed {
+                old: 1..3,
+                new_line: 1
+            }]
+        );
+        assert_eq!(
+            diff("a\nb\n", "b\n"),
+            vec![LineChange::Removed {
+                old: 0..1,
+                new_line: 0
+            }]
+        );
+
+        // In place modification
+        assert_eq!(
+            diff("a\nb\nc", "a\nB\nc"),
+            vec![LineChange::Changed {
+                old: 1..2,
+                new: 1..2
+            }]
+        );
+
+        // A mix of changes
+        assert_eq!(
+            diff("a\nb\nc\nd\ne\nf", "x\na\nc\nD\ne\nf\ng"),
+            vec![
+                LineChange::Inserted {
+                    old_line: 0,
+                    new: 0..1
+                },
+                LineChange::Removed {
+                    old: 1..2,
+                    new_line: 2
+                },
+                LineChange::Changed {
+                    old: 3..4,
+                    new: 3..4
+                },
+                LineChange::Inserted {
+                    old_line: 6,
+                    new: 6..7
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_diff_is_minimal() {
+        // The unchanged lines between the changes, checking that they really are the same
+        fn kept_lines(old: &[&str], new: &[&str], changes: &[LineChange]) -> usize {
+            let ranges = changes.iter().map(|change| match change.clone() {
+                LineChange::Inserted { old_line, new } => (old_line..old_line, new),
+                LineChange::Removed { old, new_line } => (old, new_line..new_line),
+                LineChange::Changed { old, new } => (old, new),
+            });
+            let end = (old.len()..old.len(), new.len()..new.len());
+
+            let (mut old_line, mut new_line) = (0, 0);
+            let mut kept = 0;
+            for (old_range, new_range) in ranges.chain([end]) {
+                let len = old_range.start - old_line;
+                assert_eq!(len, new_range.start - new_line);
+                assert_eq!(
+                    old[old_line..old_range.start],
+                    new[new_line..new_range.start]
+                );
+                kept += len;
+                old_line = old_range.end;
+                new_line = new_range.end;
+            }
+            kept
+        }
+
+        fn lcs_len(old: &[&str], new: &[&str]) -> usize {
+            let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    lcs[i][j] = if old[i] == new[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        lcs[i + 1][j].max(lcs[i][j + 1])
+                    };
+                }
+            }
+            lcs[0][0]
+        }
+
+        // A small generator so that the cases are the same on every run
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound) as usize
+        };
+        let words = ["a", "b", "c", "d"];
+        for _ in 0..500 {
+            let old: Vec<&str> = (0..next(12)).map(|_| words[next(4)]).collect();
+            let new: Vec<&str> = (0..next(12)).map(|_| words[next(3)]).collect();
+
+            let old_rope = Rope::from(old.join("\n"));
+            let new_rope = Rope::from(new.join("\n"));
+            let changes =
+                RopeText::new(&old_rope).line_diff(&RopeText::new(&new_rope));
+
+            // An empty text still has one (empty) line
+            let old = if old.is_empty() { vec![""] } else { old };
+            let new = if new.is_empty() { vec![""] } else { new };
+            assert_eq!(
+                kept_lines(&old, &new, &changes),
+                lcs_len(&old, &new),
+                "{old:?} {new:?}"
+            );
+        }
+
+        // Large differing regions don't need a table of every pair of lines
+        let old: String = (0..4_000).map(|i| format!("{}\n", i % 3)).collect();
+        let new: String = (0..4_000).map(|i| format!("{}\n", i % 5)).collect();
+        let old = Rope::from(old);
+        let new = Rope::from(new);
+        let changes = RopeText::new(&old).line_diff(&RopeText::new(&new));
+        assert!(!changes.is_empty());
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_ending_at(0), None);
+        assert_eq!(text.detect_line_ending(), None);
+
+        let text = Rope::from("abc");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_ending_at(0), None);
+        assert_eq!(text.detect_line_ending(), None);
+
+        let text = Rope::from("a\nb\r\nc");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_ending_at(0), Some(LineEnding::Lf));
+        assert_eq!(text.line_ending_at(1), Some(LineEnding::CrLf));
+        assert_eq!(text.line_ending_at(2), None);
+        assert_eq!(text.line_ending_at(3), None);
+        // A tie goes to LF
+        assert_eq!(text.detect_line_ending(), Some(LineEnding::Lf));
+
+        let text = Rope::from("a\r\nb\nc\r\n\r\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.detect_line_ending(), Some(LineEnding::CrLf));
+
+        // A lone carriage return is not a line ending
+        let text = Rope::from("a\rb\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_ending_at(0), Some(LineEnding::Lf));
+        assert_eq!(text.detect_line_ending(), Some(LineEnding::Lf));
+    }
+
+    #[test]
+    fn test_offset_to_line_col() {
+        for content in [
+            "",
+            "abc",
+            "abc\ndef\nghi",
+            "abc\r\ndef\r\nghi\r\n",
+            "\n\n\n",
+            "a\u{00e9}b\n\u{1f600}\nx\u{0301}y",
+        ] {
+            let text = Rope::from(content);
+            let text = RopeText::new(&text);
+
+            for offset in 0..=text.len() + 2 {
+                let clamped = offset.min(text.len());
+                let line = text.line_of_offset(clamped);
+                let line_start = text.offset_of_line(line);
+                let mut col = clamped - line_start;
+                let bytes = content.as_bytes();
+                if clamped > 0
+                    && bytes.get(clamped - 1) == Some(&b'\r')
+                    && bytes.get(clamped) == Some(&b'\n')
+                {
+                    col -= 1;
+                }
+
+                assert_eq!(
+                    text.offset_to_line_col(offset),
+                    (line, col),
+                    "{content:?} at {offset}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_offset_to_line_col_crlf() {
+        let text = Rope::from("abc\r\ndef");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_to_line_col(3), (0, 3));
+        // Between the \r and \n is treated as before the \r
+        assert_eq!(text.offset_to_line_col(4), (0, 3));
+        assert_eq!(text.offset_to_line_col(5), (1, 0));
+        assert_eq!(text.offset_to_line_col(4), (0, text.line_end_col(0, true)));
+
+        assert_eq!(text.offset_to_position(4), text.offset_to_position(3));
+        assert_eq!(text.offset_to_position(4).character, 3);
+
+        // Columns on the line after a CRLF are relative to the start of that line
+        assert_eq!(text.offset_to_line_col(6), (1, 1));
+        assert_eq!(text.offset_to_line_col(7), (1, 2));
+        assert_eq!(text.offset_to_line_col(8), (1, 3));
+        assert_eq!(text.offset_to_line_col(100), (1, 3));
+        for offset in 5..=8 {
+            let (line, col) = text.offset_to_line_col(offset);
+            assert_eq!(text.offset_of_line_col(line, col), offset);
+        }
+
+        // The column is in bytes, not chars
+        let text = Rope::from("a\r\n\u{00e9}\u{1F600}x\r\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_to_line_col(3), (1, 0));
+        assert_eq!(text.offset_to_line_col(5), (1, 2));
+        assert_eq!(text.offset_to_line_col(9), (1, 6));
+        assert_eq!(text.offset_to_line_col(10), (1, 7));
+        assert_eq!(text.offset_to_line_col(11), (1, 7));
+        assert_eq!(text.offset_to_line_col(12), (2, 0));
+    }
+
+    #[test]
+    fn test_offset_to_position() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_to_position(0), Position::new(0, 0));
+        assert_eq!(text.offset_to_position(3), Position::new(0, 0));
+
+        let text = Rope::from("a\u{1f600}b\n\u{1f600}");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_to_position(0), Position::new(0, 0));
+        assert_eq!(text.offset_to_position(1), Position::new(0, 1));
+        // Inside of the emoji is snapped back to before it
+        for offset in 2..5 {
+            assert_eq!(text.offset_to_position(offset), Position::new(0, 1));
+        }
+        assert_eq!(text.offset_to_position(5), Position::new(0, 3));
+        assert_eq!(text.offset_to_position(6), Position::new(0, 4));
+
+        assert_eq!(text.offset_to_position(7), Position::new(1, 0));
+        for offset in 8..11 {
+            assert_eq!(text.offset_to_position(offset), Position::new(1, 0));
+        }
+        assert_eq!(text.offset_to_position(11), Position::new(1, 2));
+
+        // CRLF lines, with multi-byte and astral characters
+        let text = Rope::from("\u{00e9}\u{1f600}\r\nx\u{10348}y\r\n\u{4e2d}");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_to_position(2), Position::new(0, 1));
+        assert_eq!(text.offset_to_position(6), Position::new(0, 3));
+        // Between the \r and \n is before the \r
+        assert_eq!(text.offset_to_position(7), Position::new(0, 3));
+        assert_eq!(text.offset_to_position(8), Position::new(1, 0));
+        assert_eq!(text.offset_to_position(9), Position::new(1, 1));
+        assert_eq!(text.offset_to_position(13), Position::new(1, 3));
+        assert_eq!(text.offset_to_position(14), Position::new(1, 4));
+        assert_eq!(text.offset_to_position(15), Position::new(1, 4));
+        assert_eq!(text.offset_to_position(16), Position::new(2, 0));
+        assert_eq!(text.offset_to_position(19), Position::new(2, 1));
+        // Past the end of the text is the end of the last line
+        assert_eq!(text.offset_to_position(20), Position::new(2, 1));
+        assert_eq!(text.offset_to_position(100), Position::new(2, 1));
+    }
+
+    #[test]
+    fn test_offset_to_position_equivalence() {
+        // The same as getting the line and then counting the UTF16 code units from its start
+        for content in [
+            "",
+            "abc",
+            "abc\ndef\nghi",
+            "abc\r\ndef\r\nghi\r\n",
+            "\r\n\r\n",
+            "a\u{00e9}b\n\u{1f600}\nx\u{0301}y",
+            "\u{1f600}\r\n\u{10348}\u{4e2d}\r\nz",
+        ] {
+            let text = Rope::from(content);
+            let text = RopeText::new(&text);
+
+            for offset in 0..=text.len() + 2 {
+                let clamped = offset.min(text.len());
+                if !content.is_char_boundary(clamped) {
+                    continue;
+                }
+
+                let (line, col) = text.offset_to_line_col(offset);
+                let line_start = text.offset_of_line(line);
+                let character =
+                    content[line_start..line_start + col].encode_utf16().count();
+
+                assert_eq!(
+                    text.offset_to_position(offset),
+                    Position::new(line as u32, character as u32),
+                    "{content:?} at {offset}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_offset_of_position() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_position(&Position::new(0, 0)), 0);
+        assert_eq!(text.offset_of_position(&Position::new(0, 10)), 0);
+        assert_eq!(text.offset_of_position(&Position::new(5, 10)), 0);
+
+        let text = Rope::from("ab\n\u{1F600}c\r\nd");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_position(&Position::new(0, 1)), 1);
+        assert_eq!(text.offset_of_position(&Position::new(1, 2)), 7);
+        assert_eq!(text.offset_of_position(&Position::new(1, 3)), 8);
+        // A character past the end of the line stays on that line
+        assert_eq!(text.offset_of_position(&Position::new(0, 3)), 2);
+        assert_eq!(text.offset_of_position(&Position::new(0, 50)), 2);
+        assert_eq!(text.offset_of_position(&Position::new(1, 4)), 8);
+        assert_eq!(text.offset_of_position(&Position::new(1, 50)), 8);
+        assert_eq!(text.offset_of_position(&Position::new(2, 50)), 11);
+        // A line past the last line is the end of the text
+        assert_eq!(text.offset_of_position(&Position::new(3, 0)), text.len());
+        assert_eq!(text.offset_of_position(&Position::new(3, 50)), text.len());
+        assert_eq!(
+            text.offset_of_position(&Position::new(u32::MAX, u32::MAX)),
+            text.len()
+        );
+
+        let text = Rope::from("ab\n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_position(&Position::new(0, 50)), 2);
+        assert_eq!(text.offset_of_position(&Position::new(1, 50)), 3);
+        assert_eq!(text.offset_of_position(&Position::new(2, 0)), 3);
+    }
+
+    #[test]
+    fn test_position_to_line_col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.position_to_line_col(&Position::new(0, 0)), (0, 0));
+        assert_eq!(
+            text.position_to_line_col(&Position::new(0, u32::MAX)),
+            (0, 0)
+        );
+
+        let long_line = "a".repeat(100_000);
+        let text = Rope::from(format!("{long_line}\nb\r\n{long_line}"));
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.position_to_line_col(&Position::new(0, 5)), (0, 5));
+        // A huge character stops at the end of the line, rather than going into the next
+        assert_eq!(
+            text.position_to_line_col(&Position::new(0, u32::MAX)),
+            (0, 100_000)
+        );
+        assert_eq!(
+            text.offset_of_position(&Position::new(0, u32::MAX)),
+            100_000
+        );
+        assert_eq!(
+            text.position_to_line_col(&Position::new(1, u32::MAX)),
+            (1, 1)
+        );
+        assert_eq!(
+            text.offset_of_position(&Position::new(1, u32::MAX)),
+            100_002
+        );
+        assert_eq!(
+            text.position_to_line_col(&Position::new(2, u32::MAX)),
+            (2, 100_000)
+        );
+        assert_eq!(
+            text.offset_of_position(&Position::new(2, u32::MAX)),
+            text.len()
+        );
+        assert_eq!(
+            text.try_offset_of_position(&Position::new(0, u32::MAX)),
+            Some(100_000)
+        );
+    }
+
+    #[test]
+    fn test_offset_of_line_col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_line_col(0, 0), 0);
+        assert_eq!(text.offset_of_line_col(0, 5), 0);
+        assert_eq!(text.offset_of_line_col(1, 0), 0);
+
+        let text = Rope::from("a\u{00e9} b\nxyz\r\n\u{00e9}");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_line_col(0, 0), 0);
+        // At the start of the multi-byte character
+        assert_eq!(text.offset_of_line_col(0, 1), 1);
+        // In the middle of it, which snaps back to its start
+        assert_eq!(text.offset_of_line_col(0, 2), 1);
+        // Just after it
+        assert_eq!(text.offset_of_line_col(0, 3), 3);
+        assert_eq!(text.offset_of_line_col(0, 4), 4);
+        // The end of the line, and past it, is before the line ending
+        assert_eq!(text.offset_of_line_col(0, 5), 5);
+        assert_eq!(text.offset_of_line_col(0, 6), 5);
+        assert_eq!(text.offset_of_line_col(0, 100), 5);
+
+        assert_eq!(text.offset_of_line_col(1, 3), 9);
+        // Never between the \r and \n
+        assert_eq!(text.offset_of_line_col(1, 4), 9);
+        assert_eq!(text.offset_of_line_col(1, 100), 9);
+
+        // Last line without a line ending
+        assert_eq!(text.offset_of_line_col(2, 1), 11);
+        assert_eq!(text.offset_of_line_col(2, 2), 13);
+        assert_eq!(text.offset_of_line_col(2, 100), 13);
+
+        // The end of each line round trips
+        for line in 0..3 {
+            let col = text.line_end_col(line, true);
+            let offset = text.offset_of_line_col(line, col);
+            assert_eq!(text.offset_to_line_col(offset), (line, col));
+        }
+
+        for offset in 0..=text.len() {
+            let (line, col) = text.offset_to_line_col(offset);
+            let expected = if offset == 2 || offset == 12 {
+                offset - 1
+            } else if offset == 10 {
+                9
+            } else {
+                offset
+            };
+            assert_eq!(text.offset_of_line_col(line, col), expected, "{offset}");
+        }
+    }
+
+    #[test]
+    fn test_try_offset_of_position() {
+        let text = Rope::from("a\u{1f600}b\n\u{1f600}");
+        let text = RopeText::new(&text);
+
+        let pos = |line, character| Position { line, character };
+
+        assert_eq!(text.try_offset_of_position(&pos(0, 0)), Some(0));
+        assert_eq!(text.try_offset_of_position(&pos(0, 1)), Some(1));
+        // Between the two halves of the surrogate pair
+        assert_eq!(text.try_offset_of_position(&pos(0, 2)), None);
+        assert_eq!(text.try_offset_of_position(&pos(0, 3)), Some(5));
+        assert_eq!(text.try_offset_of_position(&pos(0, 4)), Some(6));
+
+        assert_eq!(text.try_offset_of_position(&pos(1, 0)), Some(7));
+        assert_eq!(text.try_offset_of_position(&pos(1, 1)), None);
+        assert_eq!(text.try_offset_of_position(&pos(1, 2)), Some(11));
+
+        // The unchecked version snaps past the codepoint
+        assert_eq!(text.offset_of_position(&pos(0, 2)), 5);
+        assert_eq!(text.offset_of_position(&pos(1, 1)), 11);
+    }
+
+    #[test]
+    fn test_offset_and_grapheme_col_of_line_col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(0, 0), (0, 0));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(0, 5), (0, 0));
+
+        // e + combining acute, x + combining acute, y
+        let text = Rope::from("ab\ne\u{0301}x\u{0301}y\r\nz");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(0, 1), (1, 1));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(0, 2), (2, 2));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(0, 10), (2, 2));
+
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 0), (3, 0));
+        // Between the e and its combining mark snaps back to the e
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 1), (3, 0));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 2), (3, 0));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 3), (6, 1));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 4), (6, 1));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 6), (9, 2));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 7), (10, 3));
+        // The CRLF is a single grapheme, so we don't land in the middle of it
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 8), (10, 3));
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(1, 100), (10, 3));
+
+        assert_eq!(text.offset_and_grapheme_col_of_line_col(2, 1), (13, 1));
+    }
+
+    #[test]
+    fn test_offset_of_line_grapheme_col() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_line_grapheme_col(0, 0), 0);
+        assert_eq!(text.offset_of_line_grapheme_col(0, 5), 0);
+        assert_eq!(text.offset_of_line_grapheme_col(1, 0), 0);
+
+        // e + combining acute, x + combining acute, y
+        let text = Rope::from("ab\ne\u{0301}x\u{0301}y\r\nz");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.offset_of_line_grapheme_col(0, 0), 0);
+        assert_eq!(text.offset_of_line_grapheme_col(0, 1), 1);
+        assert_eq!(text.offset_of_line_grapheme_col(0, 2), 2);
+        // Past the end stays before the line ending
+        assert_eq!(text.offset_of_line_grapheme_col(0, 3), 2);
+
+        assert_eq!(text.offset_of_line_grapheme_col(1, 0), 3);
+        // Each column skips a whole cluster, where a byte column of 1 would be between
+        // the e and its combining mark
+        assert_eq!(text.offset_of_line_grapheme_col(1, 1), 6);
+        assert_eq!(text.offset_of_line_grapheme_col(1, 2), 9);
+        assert_eq!(text.offset_of_line_grapheme_col(1, 3), 10);
+        assert_eq!(text.offset_of_line_grapheme_col(1, 4), 10);
+        assert_eq!(text.offset_of_line_grapheme_col(1, 100), 10);
+        assert_eq!(text.offset_of_line_col(1, 1), 4);
+        assert_eq!(text.offset_of_line_col(1, 3), 6);
+
+        assert_eq!(text.offset_of_line_grapheme_col(2, 0), 12);
+        assert_eq!(text.offset_of_line_grapheme_col(2, 1), 13);
+        assert_eq!(text.offset_of_line_grapheme_col(2, 2), 13);
+        // Out of bounds lines are clamped to the last line
+        assert_eq!(text.offset_of_line_grapheme_col(5, 1), 13);
+
+        for grapheme_col in 0..4 {
+            let offset = text.offset_of_line_grapheme_col(1, grapheme_col);
+            assert_eq!(
+                text.offset_and_grapheme_col_of_line_col(1, offset - 3),
+                (offset, grapheme_col)
+            );
+        }
+    }
+
+    #[test]
+    fn test_scanner_line_of_offset() {
+        let check = |content: &str, offsets: &[usize]| {
+            let rope = Rope::from(content);
+            let text = RopeText::new(&rope);
+            let mut scanner = RopeTextScanner::new(&rope);
+
+            for &offset in offsets {
+                assert_eq!(
+                    scanner.line_of_offset(offset),
+                    text.line_of_offset(offset),
+                    "{offset}"
+                );
+            }
+        };
+
+        check("", &[0, 1, 0]);
+        check("a\n", &[0, 1, 2, 3, 1, 0, 2]);
+        check("abc\r\ndef\r\n\r\nghi", &[0, 4, 5, 6, 11, 12, 100, 3, 0]);
+
+        let content = (0..500)
+            .map(|i| format!("line {i} {}\n", "x".repeat(i % 7)))
+            .collect::<String>();
+
+        // Scrolling forwards and backwards over nearby offsets
+        let forward = (0..content.len()).step_by(3).collect::<Vec<_>>();
+        let backward = forward.iter().rev().copied().collect::<Vec<_>>();
+        check(&content, &forward);
+        check(&content, &backward);
+
+        // Jumps which are too far to step
+        check(
+            &content,
+            &[0, content.len(), 10, content.len() / 2, 5, 6000],
+        );
+    }
+
+    #[test]
+    fn test_line_reader() {
+        let check = |text: &RopeText, lines: &[usize]| {
+            let mut reader = text.line_reader();
+            for &line in lines {
+                assert_eq!(reader.content(line), text.line_content(line), "{line}");
+                assert_eq!(
+                    reader.start_offset(line),
+                    text.offset_of_line(line),
+                    "{line}"
+                );
+                assert_eq!(
+                    reader.end_offset(line),
+                    text.offset_of_line(line.saturating_add(1)),
+                    "{line}"
+                );
+            }
+        };
 
-    #[test]
-    fn test_line_content() {
         let text = Rope::from("");
         let text = RopeText::new(&text);
+        check(&text, &[0, 1, 2, 0]);
 
-        assert_eq!(text.line_content(0), "");
-        assert_eq!(text.line_content(1), "");
-        assert_eq!(text.line_content(2), "");
+        let rope = Rope::from("abc\r\ndef\n\nghi\n");
+        let text = RopeText::new(&rope);
+        check(&text, &[0, 1, 2, 3, 4, 5, 3, 0, usize::MAX]);
+        let text = RopeText::new(&rope).without_trailing_empty_line();
+        check(&text, &[0, 1, 2, 3, 4, 5, 3, 0, usize::MAX]);
 
-        let text = Rope::from("abc\ndef\nghi");
+        let content = (0..500)
+            .map(|i| format!("line {i} {}\n", "x".repeat(i % 7)))
+            .collect::<String>();
+        let text = Rope::from(content);
         let text = RopeText::new(&text);
 
-        assert_eq!(text.line_content(0), "abc\n");
-        assert_eq!(text.line_content(1), "def\n");
-        assert_eq!(text.line_content(2), "ghi");
-        assert_eq!(text.line_content(3), "");
-        assert_eq!(text.line_content(4), "");
-        assert_eq!(text.line_content(5), "");
-
-        let text = Rope::from("abc\r\ndef\r\nghi");
-        let text = RopeText::new(&text);
+        // Rendering a screen of lines
+        check(&text, &(100..160).collect::<Vec<_>>());
 
-        assert_eq!(text.line_content(0), "abc\r\n");
-        assert_eq!(text.line_content(1), "def\r\n");
-        assert_eq!(text.line_content(2), "ghi");
-        assert_eq!(text.line_content(3), "");
-        assert_eq!(text.line_content(4), "");
-        assert_eq!(text.line_content(5), "");
+        // Scrolling backwards, and jumps which are too far to step
+        check(&text, &(100..160).rev().collect::<Vec<_>>());
+        check(&text, &[0, 499, 500, 501, 10, 250, 5, 6000, 498]);
+        // Small steps back and forth are stepped rather than looked up
+        check(&text, &[100, 95, 110, 104, 104, 120, 103]);
     }
 
     #[test]
@@ -371,6 +4468,198 @@ fn test_offset_of_line() {
         assert_eq!(text.offset_of_line(5), text.len());
     }
 
+    #[test]
+    fn test_last_line_number_width() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.last_line_number_width(10), 1);
+        assert_eq!(text.last_line_number_width(16), 1);
+
+        let width = |num_lines: usize, base: u32| {
+            let text = Rope::from("\n".repeat(num_lines - 1));
+            RopeText::new(&text).last_line_number_width(base)
+        };
+
+        assert_eq!(width(9, 10), 1);
+        assert_eq!(width(10, 10), 2);
+        assert_eq!(width(99, 10), 2);
+        assert_eq!(width(100, 10), 3);
+        assert_eq!(width(1000, 10), 4);
+        assert_eq!(width(15, 16), 1);
+        assert_eq!(width(16, 16), 2);
+        assert_eq!(width(256, 16), 3);
+        assert_eq!(width(7, 2), 3);
+        assert_eq!(width(8, 2), 4);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "line number base must be at least 2")]
+    fn test_last_line_number_width_base_one() {
+        let text = Rope::from("a\nb");
+        let text = RopeText::new(&text);
+
+        text.last_line_number_width(1);
+    }
+
+    #[test]
+    fn test_line_offset_bounds() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_offset_bounds(0), (0, 0));
+        assert_eq!(text.line_offset_bounds(1), (0, 0));
+        assert_eq!(text.line_range_offsets(0..5), 0..0);
+
+        let rope = Rope::from("abc\r\ndef\n\nghi\n");
+        for text in [
+            RopeText::new(&rope),
+            RopeText::new(&rope).without_trailing_empty_line(),
+        ] {
+            for line in 0..7 {
+                assert_eq!(
+                    text.line_offset_bounds(line),
+                    (text.offset_of_line(line), text.offset_of_line(line + 1)),
+                    "{line}"
+                );
+            }
+            assert_eq!(text.line_offset_bounds(usize::MAX), (14, 14));
+        }
+
+        let text = RopeText::new(&rope);
+        assert_eq!(text.line_offset_bounds(2), (9, 10));
+        // The last line
+        assert_eq!(text.line_offset_bounds(3), (10, 14));
+        assert_eq!(text.line_offset_bounds(4), (14, 14));
+
+        assert_eq!(text.line_range_offsets(0..2), 0..9);
+        assert_eq!(text.line_range_offsets(1..4), 5..14);
+        assert_eq!(text.line_range_offsets(1..1), 5..5);
+        assert_eq!(text.line_range_offsets(2..100), 9..14);
+        assert_eq!(text.line_range_offsets(100..200), 14..14);
+        #[allow(clippy::reversed_empty_ranges)]
+        let inverted = 3..1;
+        assert_eq!(text.line_range_offsets(inverted), 10..10);
+    }
+
+    #[test]
+    fn test_preview_replace() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.preview_replace(0..0, ""), (0, 0));
+        assert_eq!(text.preview_replace(0..10, "ab\ncd"), (5, 1));
+
+        let content = "abc\ndef\r\nghi";
+        let check = |range: std::ops::Range<usize>, new_text: &str| {
+            let rope = Rope::from(content);
+            let text = RopeText::new(&rope);
+            let (len_delta, line_delta) =
+                text.preview_replace(range.clone(), new_text);
+
+            let mut edited = rope.clone();
+            edited.edit(range, Rope::from(new_text));
+            let edited_text = RopeText::new(&edited);
+            assert_eq!(edited.len() as isize - rope.len() as isize, len_delta);
+            assert_eq!(
+                edited_text.num_lines() as isize - text.num_lines() as isize,
+                line_delta
+            );
+            (len_delta, line_delta)
+        };
+
+        // Replacing lines with a single line
+        assert_eq!(check(2..11, "X"), (-8, -2));
+        // And a single line with several lines
+        assert_eq!(check(1..2, "1\n2\r\n3\n"), (6, 3));
+        assert_eq!(check(0..content.len(), ""), (-12, -2));
+        assert_eq!(check(4..4, "\n"), (1, 1));
+        assert_eq!(check(3..4, " "), (0, -1));
+        assert_eq!(check(5..6, "E"), (0, 0));
+
+        let text = Rope::from(content);
+        let text = RopeText::new(&text);
+        assert_eq!(text.preview_replace(10..100, ""), (-2, 0));
+    }
+
+    #[test]
+    fn test_line_start_after() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_start_after(0, 0), 0);
+        assert_eq!(text.line_start_after(0, 5), 0);
+        assert_eq!(text.line_start_after(5, 1), 0);
+
+        let content = (0..10).map(|i| format!("line {i}\r\n")).collect::<String>();
+        let text = Rope::from(content);
+        let text = RopeText::new(&text);
+
+        assert_eq!(
+            text.line_start_after(text.offset_of_line(3), 4),
+            text.offset_of_line(7)
+        );
+        for line in 0..12 {
+            for n in 0..12 {
+                let expected = text.offset_of_line(line + n);
+                // From the start of the line, and from the middle of it
+                let offset = text.offset_of_line(line);
+                assert_eq!(text.line_start_after(offset, n), expected);
+                let offset = text.line_end_offset(line, true);
+                assert_eq!(text.line_start_after(offset, n), expected);
+            }
+        }
+        assert_eq!(text.line_start_after(100, 1), text.len());
+        assert_eq!(text.line_start_after(0, usize::MAX), text.len());
+
+        let text = Rope::from("abc\ndef");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_start_after(1, 1), 4);
+        assert_eq!(text.line_start_after(1, 2), 7);
+        assert_eq!(text.line_start_after(5, 0), 4);
+    }
+
+    #[test]
+    fn test_without_trailing_empty_line() {
+        let rope = Rope::from("a\n");
+        let text = RopeText::new(&rope);
+        let trimmed = RopeText::new(&rope).without_trailing_empty_line();
+
+        assert_eq!(text.num_lines(), 2);
+        assert_eq!(trimmed.num_lines(), 1);
+        assert_eq!(text.last_line(), 1);
+        assert_eq!(trimmed.last_line(), 0);
+
+        assert_eq!(text.offset_of_line(0), 0);
+        assert_eq!(trimmed.offset_of_line(0), 0);
+        assert_eq!(text.offset_of_line(1), 2);
+        assert_eq!(trimmed.offset_of_line(1), 2);
+        assert_eq!(text.offset_of_line(text.num_lines()), 2);
+        assert_eq!(trimmed.offset_of_line(trimmed.num_lines()), 2);
+
+        let rope = Rope::from("a\n\n");
+        assert_eq!(RopeText::new(&rope).num_lines(), 3);
+        assert_eq!(
+            RopeText::new(&rope)
+                .without_trailing_empty_line()
+                .num_lines(),
+            2
+        );
+
+        // Without a final line ending there's nothing to leave out
+        for content in ["", "a", "a\nb"] {
+            let rope = Rope::from(content);
+            assert_eq!(
+                RopeText::new(&rope).num_lines(),
+                RopeText::new(&rope)
+                    .without_trailing_empty_line()
+                    .num_lines()
+            );
+        }
+    }
+
     #[test]
     fn test_line_end_offset() {
         let text = Rope::from("");
@@ -410,6 +4699,297 @@ fn test_line_end_offset() {
         assert_eq!(text.line_end_offset(2, false), 11); // "world|"
     }
 
+    #[test]
+    fn test_visual_line_count() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.wrap_points(0, 4, 4), Vec::<usize>::new());
+        assert_eq!(text.visual_line_count(4, 4), 1);
+
+        let text = Rope::from("ab\nabcdefghij\n\tab\nabcd");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.wrap_points(0, 4, 4), Vec::<usize>::new());
+        assert_eq!(text.wrap_points(1, 4, 4), vec![7, 11]);
+        // The tab fills the whole first row
+        assert_eq!(text.wrap_points(2, 4, 4), vec![15]);
+        // Exactly fits, so it does not wrap
+        assert_eq!(text.wrap_points(3, 4, 4), Vec::<usize>::new());
+
+        assert_eq!(text.visual_line_count(4, 4), 7);
+        assert_eq!(text.visual_line_count(5, 2), 5);
+        assert_eq!(text.visual_line_count(100, 4), text.num_lines());
+        // A width of zero means no wrapping
+        assert_eq!(text.visual_line_count(0, 4), text.num_lines());
+
+        let text = Rope::from("abcdef\r\nabc\r\n");
+        let text = RopeText::new(&text);
+
+        // The line ending does not take up any columns
+        assert_eq!(text.visual_line_count(3, 4), 4);
+    }
+
+    #[test]
+    fn test_wrapped_rows() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.wrapped_rows(0, 4, 4), 1);
+        assert_eq!(text.wrapped_rows(5, 4, 4), 1);
+
+        let text = Rope::from("ab\nabcd\nabcdefghijkl\nabcdefghijklm\n\tabcdef\n");
+        let text = RopeText::new(&text);
+
+        // Shorter than the width
+        assert_eq!(text.wrapped_rows(0, 4, 4), 1);
+        // Exactly the width
+        assert_eq!(text.wrapped_rows(1, 4, 4), 1);
+        // Multiples of the width
+        assert_eq!(text.wrapped_rows(2, 4, 4), 3);
+        assert_eq!(text.wrapped_rows(2, 3, 4), 4);
+        assert_eq!(text.wrapped_rows(2, 6, 4), 2);
+        assert_eq!(text.wrapped_rows(3, 4, 4), 4);
+        // The tab fills the first row
+        assert_eq!(text.wrapped_rows(4, 4, 4), 3);
+        assert_eq!(text.wrapped_rows(4, 8, 4), 2);
+        assert_eq!(text.wrapped_rows(4, 4, 2), 2);
+        // A width of zero means no wrapping
+        assert_eq!(text.wrapped_rows(3, 0, 4), 1);
+        // The empty last line
+        assert_eq!(text.wrapped_rows(5, 4, 4), 1);
+
+        for line in 0..text.num_lines() {
+            assert_eq!(
+                text.wrapped_rows(line, 4, 4),
+                text.wrap_points(line, 4, 4).len() + 1
+            );
+        }
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_inverted_ranges() {
+        let text = Rope::from("hello\nworld");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.slice_to_cow(5..2), "");
+        assert_eq!(text.slice_to_cow(50..2), "");
+        assert_eq!(text.slice_with_kind(5..2), ("".into(), false));
+        assert_eq!(text.slice_capped(5..2, 1), ("".into(), false));
+        assert_eq!(text.char_indices_iter(5..2).next(), None);
+        assert_eq!(text.char_indices_rev_iter(5..2).next(), None);
+        assert_eq!(text.sample_chars(5..2, 1).next(), None);
+        assert_eq!(text.bytes_in_range(5..2).next(), None);
+        assert_eq!(text.grapheme_count(5..2), 0);
+        assert_eq!(text.graphemes_iter(5..2).next(), None);
+        assert_eq!(text.char_count_in_range(5..2), 0);
+        assert!(text.range_is_ascii(5..2));
+        assert!(!text.range_contains_char(5..2, 'l'));
+        assert!(text.eq_range(&text, 5..2, 8..8));
+        let mut out = String::new();
+        text.write_range(5..2, &mut out).unwrap();
+        assert_eq!(out, "");
+        let re = Regex::new(".").unwrap();
+        assert_eq!(text.matches_iter(&re, 5..2).next(), None);
+        // The line of the end of the range
+        let lines: Vec<_> = text.lines_in_range(8..2).collect();
+        assert_eq!(lines, vec![(0, "hello\n".into())]);
+
+        // Starts past the end of the text
+        assert_eq!(text.char_indices_iter(50..).next(), None);
+        assert_eq!(text.char_indices_rev_iter(50..).next(), None);
+        assert_eq!(text.char_indices_iter(..50).count(), text.len());
+        assert_eq!(text.char_indices_iter(2..=4).count(), 3);
+    }
+
+    #[test]
+    fn test_slice_with_kind() {
+        let content = "abcdefghij\n".repeat(1000);
+        let text = Rope::from(content.as_str());
+        let text = RopeText::new(&text);
+
+        // Small slices are borrowed from a single leaf
+        let (slice, owned) = text.slice_with_kind(0..3);
+        assert_eq!(slice, "abc");
+        assert!(!owned);
+
+        // The whole document spans many leaves, so it has to be allocated
+        let (slice, owned) = text.slice_with_kind(0..text.len());
+        assert_eq!(slice, content);
+        assert!(owned);
+    }
+
+    #[test]
+    fn test_slice_capped() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.slice_capped(0..10, 4), ("".into(), false));
+
+        let text = Rope::from("a\u{1f600}bcd");
+        let text = RopeText::new(&text);
+
+        assert_eq!(
+            text.slice_capped(0..text.len(), 100),
+            (text.slice_to_cow(0..text.len()), false)
+        );
+        assert_eq!(
+            text.slice_capped(0..text.len(), 8),
+            ("a\u{1f600}bcd".into(), false)
+        );
+        assert_eq!(
+            text.slice_capped(0..text.len(), 7),
+            ("a\u{1f600}bc".into(), true)
+        );
+        assert_eq!(
+            text.slice_capped(0..text.len(), 5),
+            ("a\u{1f600}".into(), true)
+        );
+        // Snaps back to before the emoji rather than splitting it
+        assert_eq!(text.slice_capped(0..text.len(), 4), ("a".into(), true));
+        assert_eq!(text.slice_capped(0..text.len(), 2), ("a".into(), true));
+        assert_eq!(text.slice_capped(0..text.len(), 0), ("".into(), true));
+        assert_eq!(text.slice_capped(5..100, 2), ("bc".into(), true));
+    }
+
+    #[test]
+    fn test_find_with_position() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.find_with_position(0, "abc"), None);
+
+        let text = Rope::from("abc\nd\u{00e9}f abc\n\u{1f600}abc");
+        let text = RopeText::new(&text);
+
+        let pos = |line, character| Position { line, character };
+
+        assert_eq!(text.find_with_position(0, "abc"), Some((0, pos(0, 0))));
+        assert_eq!(text.find_with_position(1, "abc"), Some((9, pos(1, 4))));
+        assert_eq!(text.find_with_position(9, "abc"), Some((9, pos(1, 4))));
+        assert_eq!(text.find_with_position(10, "abc"), Some((17, pos(2, 2))));
+        assert_eq!(text.find_with_position(18, "abc"), None);
+        assert_eq!(text.find_with_position(0, "c\nd"), Some((2, pos(0, 2))));
+        assert_eq!(text.find_with_position(0, "xyz"), None);
+        assert_eq!(text.find_with_position(0, ""), None);
+
+        let content = "ab\r\n\u{1f600}ab\nab\r\n\r\nx\u{00e9}ab\nab";
+        let text = Rope::from(content);
+        let text = RopeText::new(&text);
+
+        // Needles spanning lines, and starting or ending within a CRLF
+        let needles = [
+            "ab",
+            "b\r\n",
+            "\nab",
+            "\n",
+            "\r",
+            "\n\u{1f600}",
+            "ab\r\n\r\nx",
+            "b\nab\r",
+            "\u{00e9}a",
+        ];
+        for needle in needles {
+            for start in 0..=text.len() + 1 {
+                let found = text.find_with_position(start, needle);
+                let offset = text.find(start, needle);
+                assert_eq!(
+                    found.map(|(offset, _)| offset),
+                    offset,
+                    "{needle:?} {start}"
+                );
+                if let Some((offset, position)) = found {
+                    assert_eq!(position, text.offset_to_position(offset));
+                }
+            }
+        }
+        assert_eq!(text.find_with_position(0, "b\nab\r"), Some((9, pos(1, 3))));
+        // Starting on the `\n` of a CRLF gives the position before the `\r`
+        assert_eq!(
+            text.find_with_position(0, "\n\u{1f600}"),
+            Some((3, pos(0, 2)))
+        );
+    }
+
+    #[test]
+    fn test_sample_chars() {
+        let text = Rope::from("abcdefgh");
+        let text = RopeText::new(&text);
+
+        assert_eq!(
+            text.sample_chars(1..7, 2).collect::<Vec<_>>(),
+            vec![(0, 'b'), (2, 'd'), (4, 'f')]
+        );
+        assert_eq!(
+            text.sample_chars(.., 3).collect::<Vec<_>>(),
+            vec![(0, 'a'), (3, 'd'), (6, 'g')]
+        );
+        assert_eq!(
+            text.sample_chars(.., 0).collect::<Vec<_>>(),
+            text.char_indices_iter(..).collect::<Vec<_>>()
+        );
+
+        let text = Rope::from("a\u{00e9}b\u{1f600}c");
+        let text = RopeText::new(&text);
+
+        assert_eq!(
+            text.sample_chars(.., 2).collect::<Vec<_>>(),
+            vec![(0, 'a'), (3, 'b'), (8, 'c')]
+        );
+    }
+
+    #[test]
+    fn test_matches_overlapping() {
+        let matches = |content: &str, needle: &str| {
+            let text = Rope::from(content);
+            let text = RopeText::new(&text);
+            text.matches_overlapping(needle).collect::<Vec<_>>()
+        };
+
+        assert_eq!(matches("aaaa", "aa"), vec![0, 1, 2]);
+        assert_eq!(matches("aaa", "aa"), vec![0, 1]);
+        assert_eq!(matches("abcabc", "abc"), vec![0, 3]);
+        assert_eq!(matches("abab\naba", "aba"), vec![0, 5]);
+        assert_eq!(
+            matches("\u{00e9}\u{00e9}\u{00e9}", "\u{00e9}\u{00e9}"),
+            vec![0, 2]
+        );
+        assert_eq!(matches("aaaa", "b"), Vec::<usize>::new());
+        assert_eq!(matches("aaaa", ""), Vec::<usize>::new());
+        assert_eq!(matches("", "a"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_line_ends() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_ends(0), (0, 0));
+        assert_eq!(text.line_ends(1), (0, 0));
+
+        let text = Rope::from("hello\nworld");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_ends(0), (4, 5));
+        assert_eq!(text.line_ends(1), (10, 11));
+        assert_eq!(text.line_ends(2), (11, 11));
+
+        for content in ["hello\nworld", "abc\r\n\r\nx\u{0301}\n", "a\n\n"] {
+            let text = Rope::from(content);
+            let text = RopeText::new(&text);
+            for line in 0..text.num_lines() + 2 {
+                assert_eq!(
+                    text.line_ends(line),
+                    (
+                        text.line_end_offset(line, false),
+                        text.line_end_offset(line, true)
+                    )
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_prev_grapheme_offset() {
         let text = Rope::from("");
@@ -427,6 +5007,168 @@ fn test_prev_grapheme_offset() {
         assert_eq!(text.prev_grapheme_offset(0, 1, 1), 0);
         assert_eq!(text.prev_grapheme_offset(2, 1, 0), 1);
         assert_eq!(text.prev_grapheme_offset(2, 1, 1), 1);
+
+        let text = Rope::from("ab\u{00e9}\u{1F600}c");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.prev_grapheme_offset(9, 10, 0), 0);
+        assert_eq!(text.prev_grapheme_offset(9, 10, 4), 4);
+        // The limit is in the middle of a multi-byte character
+        assert_eq!(text.prev_grapheme_offset(9, 10, 3), 4);
+        assert_eq!(text.prev_grapheme_offset(9, 10, 5), 8);
+        assert_eq!(text.prev_grapheme_offset(9, 10, 7), 8);
+        assert_eq!(text.prev_grapheme_offset(8, 1, 6), 8);
+        assert_eq!(text.prev_grapheme_offset(8, 1, 4), 4);
+
+        // e + combining acute, CRLF, family emoji joined by ZWJs
+        let text =
+            Rope::from("e\u{0301}\r\n\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}x");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.prev_grapheme_offset(24, 4, 0), 0);
+        // The limit is in the middle of a grapheme cluster
+        assert_eq!(text.prev_grapheme_offset(24, 4, 1), 3);
+        assert_eq!(text.prev_grapheme_offset(24, 4, 4), 5);
+        assert_eq!(text.prev_grapheme_offset(24, 4, 10), 23);
+        assert_eq!(text.prev_grapheme_offset(23, 4, 10), 23);
+    }
+
+    #[test]
+    fn test_next_grapheme_offset() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.next_grapheme_offset(0, 0, 0), 0);
+        assert_eq!(text.next_grapheme_offset(0, 1, 0), 0);
+        assert_eq!(text.next_grapheme_offset(0, 1, 1), 0);
+        assert_eq!(text.next_grapheme_offset(2, 1, 10), 0);
+
+        let text = Rope::from("abc def ghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.next_grapheme_offset(0, 0, 0), 0);
+        assert_eq!(text.next_grapheme_offset(0, 1, 0), 0);
+        assert_eq!(text.next_grapheme_offset(0, 1, 1), 1);
+        assert_eq!(text.next_grapheme_offset(2, 1, 11), 3);
+        assert_eq!(text.next_grapheme_offset(2, 3, 11), 5);
+        assert_eq!(text.next_grapheme_offset(2, 3, 4), 4);
+        assert_eq!(text.next_grapheme_offset(10, 5, 11), 11);
+        assert_eq!(text.next_grapheme_offset(20, 1, 11), 11);
+
+        // e + combining acute, CRLF, family emoji joined by ZWJs
+        let text =
+            Rope::from("e\u{0301}\r\n\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}x");
+        let text = RopeText::new(&text);
+        let len = text.len();
+
+        assert_eq!(text.next_grapheme_offset(0, 1, len), 3);
+        assert_eq!(text.next_grapheme_offset(0, 2, len), 5);
+        assert_eq!(text.next_grapheme_offset(0, 3, len), 23);
+        assert_eq!(text.next_grapheme_offset(0, 4, len), 24);
+        assert_eq!(text.next_grapheme_offset(0, 3, 22), 5);
+        // In the middle of the CRLF
+        assert_eq!(text.next_grapheme_offset(4, 1, len), 5);
+        // In the middle of the ZWJ sequence
+        assert_eq!(text.next_grapheme_offset(9, 1, len), 23);
+        assert_eq!(text.next_grapheme_offset(12, 1, len), 23);
+    }
+
+    #[test]
+    fn test_word_at_offset() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.word_at_offset(0), 0..0);
+        assert_eq!(text.word_at_offset(5), 0..0);
+
+        let text = Rope::from("foo_bar  \u{65e5}\u{672c}\u{8a9e} x");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.word_at_offset(0), 0..7);
+        assert_eq!(text.word_at_offset(3), 0..7);
+        assert_eq!(text.word_at_offset(6), 0..7);
+        // Just after the end of the word is on the whitespace after it
+        assert_eq!(text.word_at_offset(7), 7..7);
+        // On whitespace
+        assert_eq!(text.word_at_offset(8), 8..8);
+        assert_eq!(text.word_at_offset(9), 9..18);
+        // In the middle of a CJK character
+        assert_eq!(text.word_at_offset(10), 9..18);
+        assert_eq!(text.word_at_offset(13), 9..18);
+        assert_eq!(text.word_at_offset(19), 19..20);
+        // At the end of the text there is no character, so the word before it is used
+        assert_eq!(text.word_at_offset(20), 19..20);
+        assert_eq!(text.word_at_offset(100), 19..20);
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.word_boundaries(0), None);
+        assert_eq!(text.word_boundaries(5), None);
+
+        let text = Rope::from("foo_bar  na\u{ef}ve\n  x");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.word_boundaries(0), Some((0, 7)));
+        // On the underscore within the identifier
+        assert_eq!(text.word_boundaries(3), Some((0, 7)));
+        assert_eq!(text.word_boundaries(7), None);
+        // On whitespace
+        assert_eq!(text.word_boundaries(8), None);
+        // Multi-byte identifier, including the middle of the multi-byte character
+        assert_eq!(text.word_boundaries(9), Some((9, 15)));
+        assert_eq!(text.word_boundaries(12), Some((9, 15)));
+        assert_eq!(text.word_boundaries(16), None);
+        // At and past the end of the text
+        assert_eq!(text.word_boundaries(18), Some((18, 19)));
+        assert_eq!(text.word_boundaries(19), Some((18, 19)));
+        assert_eq!(text.word_boundaries(100), Some((18, 19)));
+    }
+
+    #[test]
+    fn test_indent_info_of_line() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        let info = text.indent_info_of_line(0, 4);
+        assert_eq!(info.text, "");
+        assert_eq!(info.width, 0);
+        assert!(!info.uses_tabs && !info.uses_spaces);
+
+        let text = Rope::from("\t\tabc\n    def\n \t  ghi\r\n\t\r\njkl");
+        let text = RopeText::new(&text);
+
+        // Pure tabs
+        let info = text.indent_info_of_line(0, 4);
+        assert_eq!(info.text, "\t\t");
+        assert_eq!(info.width, 8);
+        assert!(info.uses_tabs && !info.uses_spaces && !info.is_mixed());
+        assert_eq!(text.indent_info_of_line(0, 2).width, 4);
+
+        // Pure spaces
+        let info = text.indent_info_of_line(1, 4);
+        assert_eq!(info.text, "    ");
+        assert_eq!(info.width, 4);
+        assert!(!info.uses_tabs && info.uses_spaces && !info.is_mixed());
+
+        // Mixed, where the tab only extends to the next tab stop
+        let info = text.indent_info_of_line(2, 4);
+        assert_eq!(info.text, " \t  ");
+        assert_eq!(info.width, 6);
+        assert!(info.is_mixed());
+        assert_eq!(text.indent_info_of_line(2, 8).width, 10);
+
+        // The line ending is not part of the indentation
+        let info = text.indent_info_of_line(3, 4);
+        assert_eq!(info.text, "\t");
+        assert_eq!(info.width, 4);
+
+        let info = text.indent_info_of_line(4, 4);
+        assert_eq!(info.text, "");
+        assert_eq!(info.width, 0);
     }
 
     #[test]
@@ -458,4 +5200,66 @@ fn test_first_non_blank_character_on_line() {
         assert_eq!(text.first_non_blank_character_on_line(4), 10);
         assert_eq!(text.first_non_blank_character_on_line(5), 10);
     }
+
+    #[test]
+    fn test_line_content_span() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.line_content_span(0), 0..0);
+        assert_eq!(text.line_content_span(1), 0..0);
+
+        let text = Rope::from("  let a = 1; \r\n\t\n\n\tb()");
+        let text = RopeText::new(&text);
+
+        // Indented, with trailing whitespace
+        assert_eq!(text.line_content_span(0), 2..12);
+        assert_eq!(text.slice_to_cow(text.line_content_span(0)), "let a = 1;");
+        // Blank lines
+        assert_eq!(text.line_content_span(1), 15..15);
+        assert_eq!(text.line_content_span(2), 17..17);
+        // The final line without a newline
+        assert_eq!(text.line_content_span(3), 19..22);
+        assert_eq!(text.slice_to_cow(text.line_content_span(3)), "b()");
+    }
+
+    #[test]
+    fn test_last_non_blank_character_on_line() {
+        let text = Rope::from("");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.last_non_blank_character_on_line(0), 0);
+        assert_eq!(text.last_non_blank_character_on_line(1), 0);
+        assert_eq!(text.last_non_blank_character_on_line(2), 0);
+
+        let text = Rope::from("abc\ndef\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.last_non_blank_character_on_line(0), 3);
+        assert_eq!(text.last_non_blank_character_on_line(1), 7);
+        assert_eq!(text.last_non_blank_character_on_line(2), 11);
+        assert_eq!(text.last_non_blank_character_on_line(3), 11);
+        assert_eq!(text.last_non_blank_character_on_line(4), 11);
+        assert_eq!(text.last_non_blank_character_on_line(5), 11);
+
+        let text = Rope::from("abc\r\ndef\r\nghi");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.last_non_blank_character_on_line(0), 3);
+        assert_eq!(text.last_non_blank_character_on_line(1), 8);
+        assert_eq!(text.last_non_blank_character_on_line(2), 13);
+        assert_eq!(text.last_non_blank_character_on_line(3), 13);
+        assert_eq!(text.last_non_blank_character_on_line(4), 13);
+        assert_eq!(text.last_non_blank_character_on_line(5), 13);
+
+        // Trailing whitespace
+        let text = Rope::from("  a b \t\r\n   \n\u{00e9}  \n");
+        let text = RopeText::new(&text);
+
+        assert_eq!(text.last_non_blank_character_on_line(0), 5);
+        // Entirely blank
+        assert_eq!(text.last_non_blank_character_on_line(1), 9);
+        assert_eq!(text.last_non_blank_character_on_line(2), 15);
+        assert_eq!(text.last_non_blank_character_on_line(3), 18);
+    }
 }
@@ -145,9 +145,9 @@ fn layout(
         _env: &Env,
     ) -> Size {
         let data = data.editor_view_content(self.view_id);
-        let last_line = data.doc.buffer().last_line() + 1;
+        let line_number_width = data.doc.buffer().last_line_number_width(10);
         let char_width = data.config.editor_char_width(ctx.text());
-        self.width = (char_width * last_line.to_string().len() as f64).ceil();
+        self.width = (char_width * line_number_width as f64).ceil();
         let mut width = self.width + 16.0 + char_width * 2.0;
         if data.editor.compare.is_some() {
             width += self.width + char_width * 2.0;
@@ -183,7 +183,7 @@ fn paint_gutter_inline_diff(
             .doc
             .buffer()
             .line_of_offset(data.editor.cursor.offset());
-        let last_line = data.doc.buffer().last_line();
+        let line_number_width = data.doc.buffer().last_line_number_width(10);
         let width = data.config.editor_char_width(ctx.text());
 
         let mut line = 0;
@@ -218,11 +218,7 @@ fn paint_gutter_inline_diff(
                             .text()
                             .new_text_layout(
                                 content.to_string()
-                                    + &vec![
-                                        " ";
-                                        (last_line + 1).to_string().len() + 2
-                                    ]
-                                    .join("")
+                                    + &vec![" "; line_number_width + 2].join("")
                                     + " -",
                             )
                             .font(
@@ -236,8 +232,7 @@ fn paint_gutter_inline_diff(
                             )
                             .build()
                             .unwrap();
-                        let x = ((last_line + 1).to_string().len()
-                            - content.to_string().len())
+                        let x = (line_number_width - content.to_string().len())
                             as f64
                             * width;
                         let y = line_height * l as f64
@@ -281,8 +276,7 @@ fn paint_gutter_inline_diff(
                             )
                             .build()
                             .unwrap();
-                        let x = ((last_line + 1).to_string().len()
-                            - left_content.to_string().len())
+                        let x = (line_number_width - left_content.to_string().len())
                             as f64
                             * width;
                         let y = line_height * l as f64
@@ -292,8 +286,7 @@ fn paint_gutter_inline_diff(
                         ctx.draw_text(&text_layout, pos);
 
                         let right_content = right_actual_line + 1;
-                        let x = ((last_line + 1).to_string().len()
-                            - right_content.to_string().len())
+                        let x = (line_number_width - right_content.to_string().len())
                             as f64
                             * width
                             + self.width
@@ -411,8 +404,7 @@ fn paint_gutter_inline_diff(
                             })
                             .build()
                             .unwrap();
-                        let x = ((last_line + 1).to_string().len()
-                            - content.to_string().len())
+                        let x = (line_number_width - content.to_string().len())
                             as f64
                             * width
                             + self.width
@@ -514,7 +506,8 @@ fn paint_gutter_code_lens(
             )
             .min(last_line);
         let char_width = data.config.editor_char_width(ctx.text());
-        let max_line_width = (last_line + 1).to_string().len() as f64 * char_width;
+        let max_line_width =
+            data.doc.buffer().last_line_number_width(10) as f64 * char_width;
 
         let mut y = lens.height_of_line(start_line) as f64;
         for (line, line_height) in lens.iter_chunks(start_line..end_line + 1) {
@@ -690,7 +683,7 @@ fn paint_gutter(&self, data: &LapceEditorBufferData, ctx: &mut PaintCtx) {
             let char_width = data.config.editor_char_width(ctx.text());
 
             let line_label_length =
-                (last_line + 1).to_string().len() as f64 * char_width;
+                data.doc.buffer().last_line_number_width(10) as f64 * char_width;
             let last_displayed_line = (start_line + num_lines + 1).min(last_line);
 
             let sequential_line_numbers = *data.main_split.active